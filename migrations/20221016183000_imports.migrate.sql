@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS imports (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  release BLOB,
+  mode TEXT NOT NULL,
+  timestamp DATETIME NOT NULL,
+  undone DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS import_files (
+  import INTEGER NOT NULL,
+  source TEXT NOT NULL,
+  destination TEXT NOT NULL,
+  track BLOB,
+  tags TEXT NOT NULL,
+  original_tags TEXT NOT NULL,
+  FOREIGN KEY(import) REFERENCES imports(id)
+);
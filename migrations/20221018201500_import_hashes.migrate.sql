@@ -0,0 +1,2 @@
+ALTER TABLE imports ADD COLUMN source TEXT;
+ALTER TABLE import_files ADD COLUMN hash TEXT;
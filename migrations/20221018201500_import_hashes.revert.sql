@@ -0,0 +1,2 @@
+ALTER TABLE imports DROP COLUMN source;
+ALTER TABLE import_files DROP COLUMN hash;
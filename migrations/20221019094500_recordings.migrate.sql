@@ -0,0 +1 @@
+ALTER TABLE tracks ADD COLUMN recording_mbid BLOB;
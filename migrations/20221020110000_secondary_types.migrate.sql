@@ -0,0 +1 @@
+ALTER TABLE releases ADD COLUMN secondary_types TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS extras (
+  path TEXT PRIMARY KEY,
+  release BLOB,
+  FOREIGN KEY(release) REFERENCES releases(mbid)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS import_pictures (
+  import INTEGER NOT NULL,
+  source TEXT NOT NULL,
+  mime TEXT NOT NULL,
+  type INTEGER NOT NULL,
+  description TEXT NOT NULL,
+  data BLOB NOT NULL,
+  FOREIGN KEY(import) REFERENCES imports(id)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS copies (
+  path TEXT PRIMARY KEY,
+  track BLOB NOT NULL,
+  format TEXT,
+  FOREIGN KEY(track) REFERENCES tracks(mbid)
+);
@@ -0,0 +1,18 @@
+ALTER TABLE release_artists ADD COLUMN position INTEGER;
+ALTER TABLE release_artists ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_artists ADD COLUMN position INTEGER;
+ALTER TABLE track_artists ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_performers ADD COLUMN position INTEGER;
+ALTER TABLE track_performers ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_engigneers ADD COLUMN position INTEGER;
+ALTER TABLE track_engigneers ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_mixers ADD COLUMN position INTEGER;
+ALTER TABLE track_mixers ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_producers ADD COLUMN position INTEGER;
+ALTER TABLE track_producers ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_lyricists ADD COLUMN position INTEGER;
+ALTER TABLE track_lyricists ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_writers ADD COLUMN position INTEGER;
+ALTER TABLE track_writers ADD COLUMN join_phrase TEXT;
+ALTER TABLE track_composers ADD COLUMN position INTEGER;
+ALTER TABLE track_composers ADD COLUMN join_phrase TEXT;
@@ -0,0 +1,18 @@
+ALTER TABLE release_artists DROP COLUMN join_phrase;
+ALTER TABLE release_artists DROP COLUMN position;
+ALTER TABLE track_artists DROP COLUMN join_phrase;
+ALTER TABLE track_artists DROP COLUMN position;
+ALTER TABLE track_performers DROP COLUMN join_phrase;
+ALTER TABLE track_performers DROP COLUMN position;
+ALTER TABLE track_engigneers DROP COLUMN join_phrase;
+ALTER TABLE track_engigneers DROP COLUMN position;
+ALTER TABLE track_mixers DROP COLUMN join_phrase;
+ALTER TABLE track_mixers DROP COLUMN position;
+ALTER TABLE track_producers DROP COLUMN join_phrase;
+ALTER TABLE track_producers DROP COLUMN position;
+ALTER TABLE track_lyricists DROP COLUMN join_phrase;
+ALTER TABLE track_lyricists DROP COLUMN position;
+ALTER TABLE track_writers DROP COLUMN join_phrase;
+ALTER TABLE track_writers DROP COLUMN position;
+ALTER TABLE track_composers DROP COLUMN join_phrase;
+ALTER TABLE track_composers DROP COLUMN position;
@@ -0,0 +1,466 @@
+use crate::fetch::cover::{
+    dedup_covers, fetch_art, get_cover, search_covers, size_cover, size_covers,
+};
+use crate::fetch::structures::Cover;
+use crate::library::{track_filters, Delete, Filter, Store};
+use crate::models::{Extra, Format, Release, Track};
+use crate::rank::{quality_issue, rank_covers, CoverRating};
+use crate::settings::{Art, ArtType};
+use crate::theme::DialoguerTheme;
+use crate::track::file::TrackFile;
+use crate::track::picture::{
+    decode, encode, find_pictures, one_per_type, picture_ext, picture_names, read_pictures,
+    write_picture, Picture,
+};
+use crate::{DB, SETTINGS};
+use dialoguer::{Confirm, Select};
+use eyre::{eyre, Report, Result, WrapErr};
+use image::DynamicImage;
+use itertools::Itertools;
+use log::{info, trace, warn};
+use std::fmt::Display;
+use std::fs::remove_file;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
+
+static FMT: &str = "{album_artist} - {album}";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    // Fetches the art of the albums without a cover
+    Fetch,
+    // Searches a new front cover for the albums, asking which one to use
+    Replace,
+    // Embeds the images found next to the tracks
+    Embed,
+    // Saves the pictures embedded in the tracks next to them
+    Extract,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Fetch => write!(f, "fetch"),
+            Action::Replace => write!(f, "replace"),
+            Action::Embed => write!(f, "embed"),
+            Action::Extract => write!(f, "extract"),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fetch" => Ok(Action::Fetch),
+            "replace" => Ok(Action::Replace),
+            "embed" => Ok(Action::Embed),
+            "extract" => Ok(Action::Extract),
+            _ => Err(eyre!("Invalid art action: {}", s)),
+        }
+    }
+}
+
+// Proposes the best cover passing the quality checks, letting the user pick
+// any other one. Returns the cover and whether it was picked explicitly
+pub fn ask_cover(
+    theme: &DialoguerTheme,
+    covers: Vec<CoverRating>,
+    art: &Art,
+) -> Option<(Cover, bool)> {
+    let issues = covers
+        .iter()
+        .map(|CoverRating(_, c)| quality_issue(c.width, c.height, art))
+        .collect::<Vec<_>>();
+    let mut index = issues.iter().position(Option::is_none);
+    let mut explicit = false;
+    match index.map(|i| &covers[i]) {
+        Some(CoverRating(score, cover)) => info!(
+            "Using cover art for release {} - {} from {} ({}x{}, score {})",
+            cover.artist, cover.title, cover.provider, cover.width, cover.height, score
+        ),
+        None if covers.is_empty() => return None,
+        None => warn!(
+            "None of the {} covers found passes the quality checks",
+            covers.len()
+        ),
+    }
+    let covers_strs: Vec<String> = covers
+        .iter()
+        .zip(issues.iter())
+        .map(|(CoverRating(s, c), issue)| {
+            format!(
+                "{}x{} for release {} - {} from {} (score {}){}",
+                c.width,
+                c.height,
+                c.artist,
+                c.title,
+                c.provider,
+                s,
+                issue
+                    .as_ref()
+                    .map_or(String::new(), |i| format!(", rejected: {}", i))
+            )
+        })
+        .collect();
+    loop {
+        let prompt = match index {
+            Some(_) => "Proceed?",
+            None => "Pick one of them anyway?",
+        };
+        let confirmed = Confirm::with_theme(theme)
+            .with_prompt(prompt)
+            .default(index.is_some())
+            .interact()
+            .ok()?;
+        match (confirmed, index) {
+            (true, Some(_)) => break,
+            (false, None) => return None,
+            _ => {}
+        }
+
+        index = Some(
+            Select::with_theme(theme)
+                .items(&covers_strs)
+                .default(index.unwrap_or(0))
+                .interact()
+                .ok()?,
+        );
+        explicit = true;
+    }
+    index.map(|i| (covers[i].1.clone(), explicit))
+}
+
+// Takes the best cover passing the quality checks, checking the size of the
+// estimated ones only as they come up
+async fn best_cover(
+    mut covers: Vec<CoverRating>,
+    release: &Release,
+    art: &Art,
+) -> Result<Option<Cover>> {
+    while !covers.is_empty() {
+        let CoverRating(_, cover) = covers.remove(0);
+        if cover.estimated {
+            // Once sized, the cover may rank lower than the next ones
+            if let Some(cover) = size_cover(cover).await {
+                covers.extend(rank_covers(vec![vec![cover]], release)?);
+                covers.sort();
+                covers.reverse();
+            }
+            continue;
+        }
+        if quality_issue(cover.width, cover.height, art).is_none() {
+            return Ok(Some(cover));
+        }
+    }
+    Ok(None)
+}
+
+// Searches, ranks and fetches the front cover of the release. The best one
+// passing the quality checks is used straight away unless interactive
+pub async fn find_cover(
+    release: &Release,
+    sources: &[PathBuf],
+    interactive: bool,
+) -> Result<Option<DynamicImage>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let covers_by_provider = search_covers(release, sources).await?;
+    let mut covers = rank_covers(covers_by_provider, release)?;
+    if interactive {
+        // All the candidates are listed with their size
+        covers = size_covers(covers, release).await?;
+    }
+    if settings.art.deduplicate {
+        covers = dedup_covers(covers).await?;
+    }
+    loop {
+        let choice = if interactive {
+            ask_cover(&DialoguerTheme::default(), covers.clone(), &settings.art)
+        } else {
+            best_cover(covers.clone(), release, &settings.art)
+                .await?
+                .map(|c| (c, false))
+        };
+        let (cover, explicit) = match choice {
+            Some(choice) => choice,
+            None => return Ok(None),
+        };
+        match get_cover(&cover, explicit).await {
+            Ok(Some(image)) => return Ok(Some(image)),
+            Err(e) if explicit => return Err(e),
+            Err(e) => warn!("Could not fetch the cover from {}: {}", cover.provider, e),
+            Ok(None) => {}
+        }
+        // The cover turned out to be unfit once fetched, the next best one
+        // is proposed instead
+        covers.retain(|CoverRating(_, c)| c.url != cover.url);
+    }
+}
+
+// The front cover, when asked for, and the other images listed in the
+// art.types setting
+pub async fn find_art(
+    release: &Release,
+    sources: &[PathBuf],
+    interactive: bool,
+) -> Result<Vec<(ArtType, DynamicImage)>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut art = vec![];
+    if settings.art.types.contains(&ArtType::Front) {
+        match find_cover(release, sources, interactive).await? {
+            Some(image) => art.push((ArtType::Front, image)),
+            None => warn!("No album art found"),
+        }
+    }
+    match fetch_art(release).await {
+        Ok(images) => art.extend(images),
+        Err(e) => warn!("Could not fetch the other images of the release: {}", e),
+    }
+    Ok(art)
+}
+
+// Writes the images in each of the folders, returning the files to be
+// tracked as extras of the release
+pub fn save_art(
+    art: &[(ArtType, DynamicImage)],
+    folders: &[PathBuf],
+    release: &Option<String>,
+) -> Result<Vec<Extra>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let names = picture_names(
+        &art.iter().map(|(kind, _)| *kind).collect::<Vec<_>>(),
+        settings.art.image_name.as_deref().unwrap_or_default(),
+    );
+    let on_disk = art
+        .iter()
+        .map(|(kind, img)| encode(img, *kind, &settings.art.on_disk()))
+        .collect::<Result<Vec<_>>>()?;
+    let mut extras = vec![];
+    for folder in folders.iter() {
+        for (picture, name) in on_disk.iter().zip(names.iter()) {
+            if let Some(path) = write_picture(picture, folder, name)? {
+                extras.push(Extra {
+                    path,
+                    release: release.clone(),
+                });
+            }
+        }
+    }
+    Ok(extras)
+}
+
+// The images to embed in the tracks, according to the art.embed setting
+pub fn embedded(art: &[(ArtType, DynamicImage)]) -> Result<Vec<Picture>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let pictures = art
+        .iter()
+        .filter(|(kind, _)| settings.art.embed.contains(kind))
+        .map(|(kind, img)| encode(img, *kind, &settings.art.embedded))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(one_per_type(pictures))
+}
+
+// Embeds the images found next to each track, or the given ones when none
+// were saved. Returns how many tracks were written
+fn embed(tracks: &[Track], fallback: &[Picture]) -> Result<usize> {
+    let mut written = 0;
+    for track in tracks.iter() {
+        let path = track
+            .path
+            .as_ref()
+            .ok_or_else(|| eyre!("Track {:?} has no path", track.mbid))?;
+        let mut pictures = path
+            .parent()
+            .map(read_pictures)
+            .transpose()?
+            .unwrap_or_default();
+        if pictures.is_empty() {
+            pictures = fallback.to_vec();
+        }
+        if pictures.is_empty() {
+            continue;
+        }
+        let mut file = TrackFile::open(path)?;
+        file.set_pictures(pictures)?;
+        file.write()
+            .wrap_err(eyre!("Could not write tags to track: {:?}", path))?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+fn folders(tracks: &[Track]) -> Vec<PathBuf> {
+    tracks
+        .iter()
+        .filter_map(|t| t.path.as_ref()?.parent().map(|p| p.to_path_buf()))
+        .unique()
+        .collect()
+}
+
+fn paths(tracks: &[Track]) -> Vec<PathBuf> {
+    tracks.iter().filter_map(|t| t.path.clone()).collect()
+}
+
+// Saves the given art next to the tracks of the album, replacing its front
+// cover, and embeds it
+async fn store_art(
+    release: &Release,
+    tracks: &[Track],
+    art: Vec<(ArtType, DynamicImage)>,
+) -> Result<()> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let folders = folders(tracks);
+    let mut old = vec![];
+    if art.iter().any(|(kind, _)| *kind == ArtType::Front) {
+        for folder in folders.iter() {
+            old.extend(find_pictures(folder)?.into_iter().map(|(path, _)| path));
+        }
+    }
+    // The old cover is only removed once the new one has been written, unless
+    // the new one took its place, having the same format
+    let extras = save_art(&art, &folders, &release.mbid)?;
+    let old = old
+        .into_iter()
+        .filter(|path| !extras.iter().any(|e| e.path == *path))
+        .map(|path| Extra {
+            path,
+            release: release.mbid.clone(),
+        })
+        .collect::<Vec<_>>();
+    let mut tx = db.begin().await?;
+    for extra in old.iter() {
+        extra.delete(&mut tx).await?;
+    }
+    for extra in extras.iter() {
+        extra.store(&mut tx).await?;
+    }
+    tx.commit().await?;
+    for extra in old.iter() {
+        trace!("Removing cover {:?}", extra.path);
+        if let Err(e) = remove_file(&extra.path) {
+            warn!("Could not remove the old cover {:?}: {}", extra.path, e);
+        }
+    }
+    embed(tracks, &embedded(&art)?)?;
+    Ok(())
+}
+
+// Saves the pictures embedded in the first track having any next to the
+// tracks, keeping the images already there
+async fn extract_album(release: &Release, tracks: &[Track]) -> Result<usize> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut pictures = vec![];
+    for path in paths(tracks).iter() {
+        pictures = TrackFile::open(path)?.get_pictures()?;
+        if !pictures.is_empty() {
+            break;
+        }
+    }
+    let names = picture_names(
+        &pictures
+            .iter()
+            .map(|p| ArtType::from_picture_type(p.picture_type))
+            .collect::<Vec<_>>(),
+        settings.art.image_name.as_deref().unwrap_or_default(),
+    );
+    let mut extras = vec![];
+    for folder in folders(tracks).iter() {
+        for (picture, name) in pictures.iter().zip(names.iter()) {
+            let path = folder.join(format!("{}.{}", name, picture_ext(&picture.mime_type)));
+            if path.exists() {
+                info!("Not extracting {:?}, the file already exists", path);
+                continue;
+            }
+            // Checks the picture is an actual image before saving it
+            decode(&picture.data)?;
+            if let Some(path) = write_picture(picture, folder, name)? {
+                extras.push(Extra {
+                    path,
+                    release: release.mbid.clone(),
+                });
+            }
+        }
+    }
+    let mut tx = db.begin().await?;
+    for extra in extras.iter() {
+        extra.store(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(extras.len())
+}
+
+pub async fn art(action: Action, filters: Vec<&String>, dry_run: bool) -> Result<()> {
+    let start = Instant::now();
+    let tracks = Track::filter(
+        track_filters(filters)?,
+        vec![" ORDER BY tracks.release, tracks.disc, tracks.number".to_string()],
+    )
+    .await?;
+    let albums = tracks
+        .into_iter()
+        .filter_map(|t| {
+            t.release
+                .as_ref()
+                .and_then(|r| r.mbid.clone())
+                .map(|id| (id, t))
+        })
+        .into_group_map();
+    let mut done = 0;
+    for tracks in albums.into_values() {
+        let release = match tracks.first().and_then(|t| t.release.clone()) {
+            Some(r) => r,
+            None => continue,
+        };
+        let label = release.fmt(FMT)?;
+        let res = match action {
+            Action::Fetch => {
+                let mut covers = vec![];
+                for folder in folders(&tracks).iter() {
+                    covers.extend(find_pictures(folder)?);
+                }
+                if !covers.is_empty() {
+                    continue;
+                }
+                info!("{} has no cover", label);
+                if dry_run {
+                    done += 1;
+                    continue;
+                }
+                match find_art(&release, &paths(&tracks), false).await {
+                    Ok(art) if art.is_empty() => Ok(0),
+                    Ok(art) => store_art(&release, &tracks, art).await.map(|_| 1),
+                    Err(e) => Err(e),
+                }
+            }
+            Action::Replace => {
+                info!("Replacing the cover of {}", label);
+                match find_cover(&release, &paths(&tracks), true).await {
+                    Ok(Some(image)) => {
+                        let art = vec![(ArtType::Front, image)];
+                        store_art(&release, &tracks, art).await.map(|_| 1)
+                    }
+                    Ok(None) => Ok(0),
+                    Err(e) => Err(e),
+                }
+            }
+            Action::Embed => {
+                trace!("Embedding the art of {}", label);
+                embed(&tracks, &[]).map(|n| usize::from(n > 0))
+            }
+            Action::Extract => extract_album(&release, &tracks).await,
+        };
+        match res {
+            Ok(n) => done += n,
+            Err(e) => warn!("Could not {} the art of {}: {}", action, label, e),
+        }
+    }
+    match action {
+        Action::Fetch if dry_run => info!("{} albums have no cover", done),
+        Action::Extract => info!("Extracted {} pictures", done),
+        _ => info!("Processed {} albums", done),
+    }
+    info!("Done, took {:?}", start.elapsed());
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use crate::settings::Collision;
+use eyre::{bail, Result};
+use log::warn;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+// The given path with " (n)" appended to the name of the file
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+// Checks the destinations of a batch of files against each other and against
+// the files already on disk, before anything gets moved. Each entry holds the
+// current path of a file, if any, and its destination, while the vacated
+// paths are going to be freed before the batch is placed. Returns the
+// destination of each file, or None for the ones to be skipped
+pub fn resolve(
+    batch: &[(Option<PathBuf>, PathBuf)],
+    vacated: &[PathBuf],
+    strategy: Collision,
+) -> Result<Vec<Option<PathBuf>>> {
+    // Files which stay where they are can't be displaced by the others
+    let mut taken: HashSet<PathBuf> = batch
+        .iter()
+        .filter(|(current, dest)| current.as_ref() == Some(dest))
+        .map(|(_, dest)| dest.clone())
+        .collect();
+    // A file is never in its own way, so that renamed files stay put
+    let is_free = |path: &Path, current: Option<&PathBuf>, taken: &HashSet<PathBuf>| {
+        !taken.contains(path)
+            && (!path.exists()
+                || current.is_some_and(|c| c == path)
+                || vacated.iter().any(|v| v == path))
+    };
+
+    let mut conflicts = 0;
+    let mut resolved = vec![];
+    for (current, dest) in batch.iter() {
+        if current.as_ref() == Some(dest) {
+            resolved.push(Some(dest.clone()));
+            continue;
+        }
+        if is_free(dest, current.as_ref(), &taken) {
+            taken.insert(dest.clone());
+            resolved.push(Some(dest.clone()));
+            continue;
+        }
+        let mut n = 1;
+        while !is_free(&numbered(dest, n), current.as_ref(), &taken) {
+            n += 1;
+        }
+        let renamed = numbered(dest, n);
+        // Renamed by a previous run already
+        if strategy == Collision::Rename && current.as_ref() == Some(&renamed) {
+            taken.insert(renamed.clone());
+            resolved.push(Some(renamed));
+            continue;
+        }
+        conflicts += 1;
+        let source = current.as_ref().unwrap_or(dest);
+        if taken.contains(dest) {
+            warn!(
+                "{:?} would be placed at {:?}, like another file of the same batch",
+                source, dest
+            );
+        } else {
+            warn!(
+                "{:?} would be placed at {:?}, overwriting an existing file",
+                source, dest
+            );
+        }
+        match strategy {
+            Collision::Abort => resolved.push(None),
+            Collision::Skip => {
+                warn!("Skipping {:?}", source);
+                resolved.push(None);
+            }
+            Collision::Rename => {
+                warn!("Placing {:?} at {:?} instead", source, renamed);
+                taken.insert(renamed.clone());
+                resolved.push(Some(renamed));
+            }
+        }
+    }
+    if strategy == Collision::Abort && conflicts > 0 {
+        bail!(
+            "{} files would overwrite other files, aborting. Change the path template or the paths.collisions setting",
+            conflicts
+        );
+    }
+    Ok(resolved)
+}
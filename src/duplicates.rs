@@ -0,0 +1,480 @@
+use crate::fetch::get;
+use crate::library::{Delete, Filter, Store};
+use crate::models::{Format, Track, TrackCopy};
+use crate::remove::{delete_files, remove_tracks};
+use crate::settings::{Duplicates, Quality};
+use crate::sync::MB_RATE_LIMIT;
+use crate::theme::DialoguerTheme;
+use crate::track::file::TrackFile;
+use crate::track::format::Format as TrackFormat;
+use crate::track::key::TagKey;
+use crate::{DB, SETTINGS};
+use dialoguer::Confirm;
+use eyre::{eyre, Report, Result};
+use itertools::Itertools;
+use log::{info, warn};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::metadata;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+static FMT_RELEASE: &str = "{album_artist} - {album} ({release_year})";
+static FMT_TRACK: &str = "{album_artist} - {album} - {track_title}";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum By {
+    Release,
+    ReleaseGroup,
+    Recording,
+    // The AcoustID stored in the tags of the files
+    Fingerprint,
+}
+
+impl Display for By {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            By::Release => write!(f, "release"),
+            By::ReleaseGroup => write!(f, "release-group"),
+            By::Recording => write!(f, "recording"),
+            By::Fingerprint => write!(f, "fingerprint"),
+        }
+    }
+}
+
+impl FromStr for By {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "release" => Ok(By::Release),
+            "release-group" => Ok(By::ReleaseGroup),
+            "recording" => Ok(By::Recording),
+            "fingerprint" => Ok(By::Fingerprint),
+            _ => Err(eyre!("Invalid duplicate criteria: {}", s)),
+        }
+    }
+}
+
+// One of the copies of some music, either a whole release or a single track.
+// Candidates without tracks are made of the copies kept next to them
+struct Candidate {
+    label: String,
+    tracks: Vec<Track>,
+    // For kept copies, the tracks they are a copy of pointing to their files,
+    // so that they can take their place in the library
+    copy_of: Vec<Track>,
+    paths: Vec<PathBuf>,
+    format: Option<TrackFormat>,
+    // in kbps, estimated from the size of the files
+    bitrate: Option<u64>,
+}
+
+impl Candidate {
+    fn new(label: String, tracks: Vec<Track>, paths: Vec<PathBuf>, length: Duration) -> Self {
+        let format = tracks
+            .iter()
+            .find_map(|t| t.format)
+            .or_else(|| paths.first().and_then(|p| TrackFormat::from_path(p).ok()));
+        let size: u64 = paths
+            .iter()
+            .filter_map(|p| metadata(p).ok())
+            .map(|m| m.len())
+            .sum();
+        let bitrate = match length.as_secs() {
+            0 => None,
+            secs => Some(size * 8 / secs / 1000),
+        };
+        Candidate {
+            label,
+            tracks,
+            copy_of: vec![],
+            paths,
+            format,
+            bitrate,
+        }
+    }
+
+    fn from_tracks(label: String, tracks: Vec<Track>) -> Self {
+        let paths = tracks.iter().filter_map(|t| t.path.clone()).collect();
+        let length = tracks.iter().filter_map(|t| t.length).sum();
+        Self::new(label, tracks, paths, length)
+    }
+
+    fn exists(&self) -> bool {
+        self.paths.iter().any(|p| p.exists())
+    }
+
+    // Lower is better
+    fn compare(&self, other: &Candidate, settings: &Duplicates) -> Ordering {
+        let format_rank = |c: &Candidate| {
+            c.format
+                .and_then(|f| settings.formats.iter().position(|s| *s == String::from(f)))
+                .unwrap_or(usize::MAX)
+        };
+        settings
+            .quality
+            .iter()
+            .map(|q| match q {
+                Quality::Format => format_rank(self).cmp(&format_rank(other)),
+                Quality::Bitrate => other.bitrate.cmp(&self.bitrate),
+            })
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let folder = self
+            .paths
+            .first()
+            .and_then(|p| p.parent())
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        write!(
+            f,
+            "{} [{}, {}]{} {}",
+            self.label,
+            self.format.map_or("unknown".to_string(), String::from),
+            self.bitrate
+                .map_or("? kbps".to_string(), |b| format!("{} kbps", b)),
+            if self.tracks.is_empty() {
+                " (copy)"
+            } else {
+                ""
+            },
+            folder
+        )
+    }
+}
+
+struct Group {
+    by: By,
+    key: String,
+    candidates: Vec<Candidate>,
+}
+
+fn release_label(tracks: &[Track]) -> Result<String> {
+    tracks
+        .first()
+        .and_then(|t| t.release.as_ref())
+        .map_or(Ok("(unknown release)".to_string()), |r| r.fmt(FMT_RELEASE))
+}
+
+// Kept copies by folder, along with the tracks they are a copy of
+type Folders<'a> = HashMap<PathBuf, Vec<(TrackCopy, &'a Track)>>;
+
+// Releases imported again while keeping both, the album tracked by the
+// library against each folder of copies kept next to it
+async fn by_release(tracks: &[Track]) -> Result<Vec<Group>> {
+    let by_mbid: HashMap<&String, &Track> = tracks
+        .iter()
+        .filter_map(|t| t.mbid.as_ref().map(|id| (id, t)))
+        .collect();
+    let mut copies: HashMap<String, Folders> = HashMap::new();
+    for copy in TrackCopy::filter::<String, String>(vec![], vec![]).await? {
+        let track = match copy.track.as_ref().and_then(|id| by_mbid.get(id)) {
+            Some(t) => *t,
+            None => continue,
+        };
+        let release = match track.release.as_ref().and_then(|r| r.mbid.clone()) {
+            Some(r) => r,
+            None => continue,
+        };
+        let folder = copy
+            .path
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
+        copies
+            .entry(release)
+            .or_default()
+            .entry(folder)
+            .or_default()
+            .push((copy, track));
+    }
+    let releases = tracks
+        .iter()
+        .filter_map(|t| {
+            t.release
+                .as_ref()
+                .and_then(|r| r.mbid.clone())
+                .map(|id| (id, t.clone()))
+        })
+        .into_group_map();
+    let mut groups = vec![];
+    for (release, folders) in copies.into_iter() {
+        let tracked = releases.get(&release).cloned().unwrap_or_default();
+        let label = release_label(&tracked)?;
+        let mut candidates = vec![Candidate::from_tracks(label.clone(), tracked)];
+        for files in folders.into_values() {
+            let length = files.iter().filter_map(|(_, t)| t.length).sum();
+            let copy_of = files
+                .iter()
+                .map(|(c, t)| Track {
+                    path: Some(c.path.clone()),
+                    format: c.format.or(t.format),
+                    ..(*t).clone()
+                })
+                .collect();
+            let paths = files.into_iter().map(|(c, _)| c.path).collect();
+            let mut candidate = Candidate::new(label.clone(), vec![], paths, length);
+            candidate.copy_of = copy_of;
+            candidates.push(candidate);
+        }
+        groups.push(Group {
+            by: By::Release,
+            key: release,
+            candidates,
+        });
+    }
+    Ok(groups)
+}
+
+fn by_release_group(tracks: &[Track]) -> Result<Vec<Group>> {
+    let release_groups = tracks
+        .iter()
+        .filter_map(|t| {
+            t.release
+                .as_ref()
+                .and_then(|r| r.release_group_mbid.clone())
+                .map(|id| (id, t.clone()))
+        })
+        .into_group_map();
+    let mut groups = vec![];
+    for (release_group, tracks) in release_groups.into_iter() {
+        let releases = tracks
+            .into_iter()
+            .filter_map(|t| {
+                t.release
+                    .as_ref()
+                    .and_then(|r| r.mbid.clone())
+                    .map(|id| (id, t))
+            })
+            .into_group_map();
+        if releases.len() < 2 {
+            continue;
+        }
+        groups.push(Group {
+            by: By::ReleaseGroup,
+            key: release_group,
+            candidates: releases
+                .into_values()
+                .map(|tracks| Ok(Candidate::from_tracks(release_label(&tracks)?, tracks)))
+                .collect::<Result<Vec<_>>>()?,
+        });
+    }
+    Ok(groups)
+}
+
+fn by_track_key<F>(tracks: &[Track], by: By, key: F) -> Result<Vec<Group>>
+where
+    F: Fn(&Track) -> Option<String>,
+{
+    let mut groups = vec![];
+    for (k, tracks) in tracks
+        .iter()
+        .filter_map(|t| key(t).map(|k| (k, t.clone())))
+        .into_group_map()
+        .into_iter()
+    {
+        if tracks.len() < 2 {
+            continue;
+        }
+        groups.push(Group {
+            by,
+            key: k,
+            candidates: tracks
+                .into_iter()
+                .map(|t| Ok(Candidate::from_tracks(t.fmt(FMT_TRACK)?, vec![t])))
+                .collect::<Result<Vec<_>>>()?,
+        });
+    }
+    Ok(groups)
+}
+
+fn acoustid(track: &Track) -> Option<String> {
+    let path = track.path.as_ref()?;
+    match TrackFile::open(path) {
+        Ok(file) => file.get_tag(TagKey::AcoustidID).into_iter().next(),
+        Err(e) => {
+            warn!("Could not read the fingerprint of {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+// The library entries of the files of a candidate made of kept copies
+fn copies(candidate: &Candidate) -> Vec<TrackCopy> {
+    candidate
+        .copy_of
+        .iter()
+        .filter_map(|t| {
+            Some(TrackCopy {
+                path: t.path.clone()?,
+                track: t.mbid.clone(),
+                format: t.format,
+            })
+        })
+        .collect()
+}
+
+// Tracks the files of a kept copy in place of the ones it is a copy of,
+// returning the ids of the tracks which now point to it
+async fn adopt(candidate: &Candidate) -> Result<HashSet<String>> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    for track in candidate.copy_of.iter() {
+        track.store(&mut tx).await?;
+    }
+    for copy in copies(candidate).iter() {
+        copy.delete(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(candidate
+        .copy_of
+        .iter()
+        .filter_map(|t| t.mbid.clone())
+        .collect())
+}
+
+// Drops a kept copy from the library, then deletes its files
+async fn discard(candidate: &Candidate) -> Result<()> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    for copy in copies(candidate).iter() {
+        copy.delete(&mut tx).await?;
+    }
+    tx.commit().await?;
+    delete_files(&candidate.paths)
+}
+
+// Tracks imported before recordings were stored have no recording id, which
+// is looked up on MusicBrainz and stored, unless on a dry run
+async fn backfill_recordings(tracks: &mut [Track], dry_run: bool) -> Result<()> {
+    let releases = tracks
+        .iter()
+        .filter(|t| t.recording_mbid.is_none())
+        .filter_map(|t| t.release.as_ref().and_then(|r| r.mbid.clone()))
+        .unique()
+        .collect::<Vec<_>>();
+    if releases.is_empty() {
+        return Ok(());
+    }
+    info!("Fetching the recordings of {} releases", releases.len());
+    let mut recordings = HashMap::new();
+    for (i, release) in releases.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(MB_RATE_LIMIT).await;
+        }
+        match get(release).await {
+            Ok((_, fetched)) => recordings.extend(
+                fetched
+                    .into_iter()
+                    .filter_map(|t| Some((t.mbid?, t.recording_mbid?))),
+            ),
+            Err(e) => warn!(
+                "Could not fetch the recordings of release {}: {}",
+                release, e
+            ),
+        }
+    }
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    for track in tracks.iter_mut().filter(|t| t.recording_mbid.is_none()) {
+        let recording = match track.mbid.as_ref().and_then(|id| recordings.get(id)) {
+            Some(r) => r.clone(),
+            None => continue,
+        };
+        if !dry_run {
+            sqlx::query("UPDATE tracks SET recording_mbid = ? WHERE mbid = ?")
+                .bind(&recording)
+                .bind(&track.mbid)
+                .execute(&mut tx)
+                .await?;
+        }
+        track.recording_mbid = Some(recording);
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn duplicates(by: Vec<By>, dry_run: bool) -> Result<()> {
+    let start = Instant::now();
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut tracks = Track::filter::<String, String>(
+        vec![],
+        vec![" ORDER BY tracks.release, tracks.disc, tracks.number".to_string()],
+    )
+    .await?;
+    if by.contains(&By::Recording) {
+        backfill_recordings(&mut tracks, dry_run).await?;
+    }
+    let mut groups = vec![];
+    for criteria in by.iter() {
+        groups.extend(match criteria {
+            By::Release => by_release(&tracks).await?,
+            By::ReleaseGroup => by_release_group(&tracks)?,
+            By::Recording => by_track_key(&tracks, By::Recording, |t| t.recording_mbid.clone())?,
+            By::Fingerprint => by_track_key(&tracks, By::Fingerprint, acoustid)?,
+        });
+    }
+    info!("Found {} groups of duplicates", groups.len());
+
+    let theme = DialoguerTheme::default();
+    for mut group in groups.into_iter() {
+        // Copies may have been removed while resolving a previous group
+        group.candidates.retain(Candidate::exists);
+        if group.candidates.len() < 2 {
+            continue;
+        }
+        group
+            .candidates
+            .sort_by(|a, b| a.compare(b, &settings.duplicates));
+        println!("{} {}:", group.by, group.key);
+        for (i, candidate) in group.candidates.iter().enumerate() {
+            println!("  {} {}", if i == 0 { "*" } else { " " }, candidate);
+        }
+        if dry_run {
+            continue;
+        }
+        if !Confirm::with_theme(&theme)
+            .with_prompt(format!(
+                "Keep the best copy (*) and delete the other {}?",
+                group.candidates.len() - 1
+            ))
+            .default(false)
+            .interact()?
+        {
+            continue;
+        }
+        // The library keeps tracking the release when a kept copy is the
+        // best one, through its files
+        let adopted = match group.candidates.first() {
+            Some(best) if best.tracks.is_empty() => Some(adopt(best).await?),
+            _ => None,
+        };
+        for candidate in group.candidates.iter().skip(1) {
+            if candidate.tracks.is_empty() {
+                discard(candidate).await?;
+                continue;
+            }
+            match adopted.as_ref() {
+                // Tracks missing from the adopted copy keep their files
+                Some(adopted) => delete_files(
+                    &candidate
+                        .tracks
+                        .iter()
+                        .filter(|t| t.mbid.as_ref().is_some_and(|id| adopted.contains(id)))
+                        .filter_map(|t| t.path.clone())
+                        .collect::<Vec<_>>(),
+                )?,
+                None => remove_tracks(&candidate.tracks, true).await?,
+            }
+        }
+    }
+    info!("Done, took {:?}", start.elapsed());
+    Ok(())
+}
@@ -1,21 +1,46 @@
-use crate::fetch::structures::Itunes;
+use crate::fetch::structures::{
+    itunes_artwork, Bandcamp, BandcampSearch, Deezer, DiscogsRelease, DiscogsSearch, FanartTv,
+    Itunes, ITUNES_SIZES,
+};
 use crate::models::Artists;
-use crate::settings::ArtProvider;
+use crate::rank::{quality_issue, rank_covers, CoverRating};
+use crate::settings::{ArtProvider, ArtType};
+use crate::track::file::TrackFile;
+use crate::track::format::Format as TrackFormat;
+use crate::track::picture::{decode, Picture, PictureType};
 use crate::{Settings, SETTINGS};
 use eyre::{bail, eyre, Result};
-use image::imageops::{resize, FilterType};
-use image::ImageOutputFormat;
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
+use image::imageops::FilterType;
 use image::{io::Reader as ImageReader, DynamicImage};
-use log::{debug, trace};
-use mime::Mime;
+use lazy_static::lazy_static;
+use log::{debug, trace, warn};
+use reqwest::header::{AUTHORIZATION, USER_AGENT};
+use reqwest::RequestBuilder;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fs::{read, read_dir};
 use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::Instant;
 
 use super::structures::Cover;
 use super::structures::CoverArtArchive;
-use super::CLIENT;
+use super::{CLIENT, MB_USER_AGENT};
 
 static DEFAULT_COUNTRY: &str = "US";
+// Covers whose size is being probed at once
+const PROBE_CONCURRENCY: usize = 16;
+// Discogs releases looked up for their images
+static DISCOGS_COUNT: usize = 5;
+static LOCAL_COVER_NAMES: &[&str] = &["cover", "folder", "front", "album", "albumart"];
+static LOCAL_COVER_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+// Side of the grid perceptual hashes are computed on
+const HASH_SIZE: u32 = 8;
+// Bits two hashes can differ in while still being the same picture
+const MAX_HASH_DISTANCE: u32 = 10;
 static ITUNES_COUNTRIES: &[&str] = &[
     "AE", "AG", "AI", "AL", "AM", "AO", "AR", "AT", "AU", "AZ", "BB", "BE", "BF", "BG", "BH", "BJ",
     "BM", "BN", "BO", "BR", "BS", "BT", "BW", "BY", "BZ", "CA", "CG", "CH", "CL", "CN", "CO", "CR",
@@ -29,11 +54,73 @@ static ITUNES_COUNTRIES: &[&str] = &[
     "UG", "US", "UY", "UZ", "VC", "VE", "VG", "VN", "YE", "ZA", "ZW",
 ];
 
-pub async fn probe(url: String) -> Option<()> {
-    CLIENT.head(url).send().await.ok().map(|_| ())
+lazy_static! {
+    // Whether the probed urls exist, as the same artworks are found again
+    // for other releases of the same album
+    static ref PROBES: Mutex<HashMap<String, bool>> = Mutex::new(HashMap::new());
 }
 
-pub async fn fetch_itunes(release: &crate::models::Release, _: &Settings) -> Result<Vec<Cover>> {
+pub async fn probe(url: String) -> bool {
+    if let Some(found) = PROBES.lock().ok().and_then(|p| p.get(&url).copied()) {
+        return found;
+    }
+    let found = match CLIENT.head(&url).send().await {
+        Ok(res) => res.status().is_success(),
+        Err(e) => {
+            debug!("Could not probe {}: {}", url, e);
+            // Not cached, the error may be temporary
+            return false;
+        }
+    };
+    if let Ok(mut probes) = PROBES.lock() {
+        probes.insert(url, found);
+    }
+    found
+}
+
+// Replaces the estimated size of a cover with the largest one actually
+// available, probing all of them at once. None when there is none
+pub async fn size_cover(mut cover: Cover) -> Option<Cover> {
+    if !cover.estimated {
+        return Some(cover);
+    }
+    let artwork = cover.thumbnail.clone()?;
+    let found = join_all(
+        ITUNES_SIZES
+            .iter()
+            .map(|size| probe(itunes_artwork(&artwork, *size))),
+    )
+    .await;
+    let size = ITUNES_SIZES
+        .iter()
+        .zip(found)
+        .find(|(_, found)| *found)
+        .map(|(size, _)| *size)?;
+    cover.url = itunes_artwork(&artwork, size);
+    cover.width = size;
+    cover.height = size;
+    cover.estimated = false;
+    Some(cover)
+}
+
+// Checks the size of all the estimated covers at once, rating them again
+pub async fn size_covers(
+    covers: Vec<CoverRating>,
+    release: &crate::models::Release,
+) -> Result<Vec<CoverRating>> {
+    let start = Instant::now();
+    let sized = stream::iter(covers.into_iter().map(|CoverRating(_, c)| size_cover(c)))
+        .buffered(PROBE_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    trace!("Cover size probing took {:?}", start.elapsed());
+    rank_covers(vec![sized.into_iter().flatten().collect()], release)
+}
+
+pub async fn fetch_itunes(
+    release: &crate::models::Release,
+    settings: &Settings,
+) -> Result<Vec<Cover>> {
     let start = Instant::now();
     let raw_country = release.country.as_deref().unwrap_or(DEFAULT_COUNTRY);
     let country = if ITUNES_COUNTRIES.contains(&raw_country) {
@@ -44,7 +131,8 @@ pub async fn fetch_itunes(release: &crate::models::Release, _: &Settings) -> Res
 
     let res = CLIENT
         .get(format!(
-            "http://itunes.apple.com/search?media=music&entity=album&country={}&term={}",
+            "{}/search?media=music&entity=album&country={}&term={}",
+            settings.art.itunes_url,
             country,
             release.artists.joined() + " " + release.title.as_str()
         ))
@@ -59,34 +147,22 @@ pub async fn fetch_itunes(release: &crate::models::Release, _: &Settings) -> Res
             res.text().await?
         );
     }
-    let mut json = res.json::<Itunes>().await?;
-    for item in json.results.iter_mut() {
-        for size in [5000, 1200, 600] {
-            let url = item
-                .artwork_url_100
-                .replace("100x100", format!("{}x{}", size, size).as_str());
-            match probe(url).await {
-                Some(_) => {
-                    item.max_size = Some(size);
-                    break;
-                }
-                None => continue,
-            }
-        }
-    }
+    // Sizes are only probed for the covers being shown, see size_cover
+    let json = res.json::<Itunes>().await?;
     let json_time = start.elapsed();
     trace!("Itunes JSON parse took {:?}", json_time - req_time);
     Ok(json.into())
 }
 
-pub async fn fetch_cover_art_archive(
+async fn cover_art_archive(
     release: &crate::models::Release,
     settings: &Settings,
-) -> Result<Vec<Cover>> {
+) -> Result<CoverArtArchive> {
     let start = Instant::now();
     let res = CLIENT
         .get(format!(
-            "http://coverartarchive.org/{}/{}",
+            "{}/{}/{}",
+            settings.art.cover_art_archive_url,
             if settings.art.cover_art_archive_use_release_group {
                 "release-group"
             } else {
@@ -116,16 +192,276 @@ pub async fn fetch_cover_art_archive(
     let json = res.json::<CoverArtArchive>().await?;
     let json_time = start.elapsed();
     trace!("CoverArtArchive JSON parse took {:?}", json_time - req_time);
-    Ok(json.into(release.title.clone(), release.artists.joined()))
+    Ok(json)
 }
 
-pub async fn search_covers(release: &crate::models::Release) -> Result<Vec<Vec<Cover>>> {
+pub async fn fetch_cover_art_archive(
+    release: &crate::models::Release,
+    settings: &Settings,
+) -> Result<Vec<Cover>> {
+    Ok(cover_art_archive(release, settings)
+        .await?
+        .into(release.title.clone(), release.artists.joined()))
+}
+
+// Sends a request to a cover provider and decodes its JSON response
+async fn get_json<T>(provider: ArtProvider, req: RequestBuilder) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let start = Instant::now();
+    let res = req.send().await?;
+    let req_time = start.elapsed();
+    trace!("{} HTTP request took {:?}", provider, req_time);
+    if !res.status().is_success() {
+        bail!(
+            "{} request returned non-success error code: {} {}",
+            provider,
+            res.status(),
+            res.text().await?
+        );
+    }
+    let json = res.json::<T>().await?;
+    trace!(
+        "{} JSON parse took {:?}",
+        provider,
+        start.elapsed() - req_time
+    );
+    Ok(json)
+}
+
+pub async fn fetch_deezer(
+    release: &crate::models::Release,
+    settings: &Settings,
+) -> Result<Vec<Cover>> {
+    let query = format!(
+        "artist:\"{}\" album:\"{}\"",
+        release.artists.joined(),
+        release.title
+    );
+    let req = CLIENT
+        .get(format!("{}/search/album", settings.art.deezer_url))
+        .query(&[("q", query)]);
+    Ok(get_json::<Deezer>(ArtProvider::Deezer, req).await?.into())
+}
+
+pub async fn fetch_bandcamp(
+    release: &crate::models::Release,
+    settings: &Settings,
+) -> Result<Vec<Cover>> {
+    let req = CLIENT
+        .post(format!(
+            "{}/api/bcsearch_public_api/1/autocomplete_elastic",
+            settings.art.bandcamp_url
+        ))
+        .json(&BandcampSearch {
+            search_text: release.artists.joined() + " " + release.title.as_str(),
+            search_filter: "a".to_string(),
+            full_page: false,
+            fan_id: None,
+        });
+    Ok(get_json::<Bandcamp>(ArtProvider::Bandcamp, req)
+        .await?
+        .into())
+}
+
+// Searches the releases on Discogs, and then looks up the images of the
+// first few results, as the search only has thumbnails
+pub async fn fetch_discogs(
+    release: &crate::models::Release,
+    settings: &Settings,
+) -> Result<Vec<Cover>> {
+    let token = settings.art.discogs_token.as_ref().ok_or(eyre!(
+        "The Discogs cover provider requires art.discogs_token"
+    ))?;
+    let auth = format!("Discogs token={}", token);
+    let req = CLIENT
+        .get(format!("{}/database/search", settings.art.discogs_url))
+        .query(&[
+            ("type", "release"),
+            ("artist", release.artists.joined().as_str()),
+            ("release_title", release.title.as_str()),
+        ])
+        .header(USER_AGENT, MB_USER_AGENT)
+        .header(AUTHORIZATION, &auth);
+    let search = get_json::<DiscogsSearch>(ArtProvider::Discogs, req).await?;
+    let releases = join_all(search.results.into_iter().take(DISCOGS_COUNT).map(|r| {
+        let req = CLIENT
+            .get(format!("{}/releases/{}", settings.art.discogs_url, r.id))
+            .header(USER_AGENT, MB_USER_AGENT)
+            .header(AUTHORIZATION, &auth);
+        get_json::<DiscogsRelease>(ArtProvider::Discogs, req)
+    }))
+    .await;
+    let mut covers = vec![];
+    for release in releases.into_iter() {
+        match release {
+            Ok(r) => covers.extend(Vec::<Cover>::from(r)),
+            Err(e) => debug!("Could not look up a Discogs release: {}", e),
+        }
+    }
+    Ok(covers)
+}
+
+pub async fn fetch_fanart_tv(
+    release: &crate::models::Release,
+    settings: &Settings,
+) -> Result<Vec<Cover>> {
+    let key = settings.art.fanart_tv_api_key.as_ref().ok_or(eyre!(
+        "The fanart.tv cover provider requires art.fanart_tv_api_key"
+    ))?;
+    let release_group = release.release_group_mbid.as_ref().ok_or(eyre!(
+        "The given release doesn't have an associated MusicBrainz relese-group id"
+    ))?;
+    let req = CLIENT
+        .get(format!(
+            "{}/v3/music/albums/{}",
+            settings.art.fanart_tv_url, release_group
+        ))
+        .query(&[("api_key", key)]);
+    Ok(get_json::<FanartTv>(ArtProvider::FanartTv, req)
+        .await?
+        .into(release.title.clone(), release.artists.joined()))
+}
+
+// Fetches the images of the release other than the front cover, like its
+// back cover or booklet, in the order of the art.types setting
+pub async fn fetch_art(release: &crate::models::Release) -> Result<Vec<(ArtType, DynamicImage)>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let types = settings
+        .art
+        .types
+        .iter()
+        .filter(|t| **t != ArtType::Front)
+        .cloned()
+        .collect::<Vec<_>>();
+    if types.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut urls = cover_art_archive(release, settings).await?.art(&types);
+    urls.sort_by_key(|(kind, _)| types.iter().position(|t| t == kind));
+    let mut images = vec![];
+    for (kind, url) in urls.into_iter() {
+        match download(&url).await.and_then(|bytes| decode(&bytes)) {
+            Ok(img) => images.push((kind, img)),
+            Err(e) => warn!("Could not fetch the {} image {}: {}", kind, url, e),
+        }
+    }
+    Ok(images)
+}
+
+// Image files named like a cover in the given folder
+fn local_cover_files(folder: &Path, settings: &Settings) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in read_dir(folder)? {
+        let path = entry?.path();
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let ext = path
+            .extension()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if path.is_file()
+            && LOCAL_COVER_EXTENSIONS.contains(&ext.as_str())
+            && (LOCAL_COVER_NAMES.contains(&stem.as_str())
+                || settings.art.image_name.as_ref().map(|n| n.to_lowercase()) == Some(stem))
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// The picture embedded in a file which is most likely to be its front cover
+fn embedded_cover(pictures: Vec<Picture>) -> Option<Picture> {
+    let front = pictures
+        .iter()
+        .position(|p| p.picture_type == PictureType::CoverFront)
+        .unwrap_or(0);
+    pictures.into_iter().nth(front)
+}
+
+// Covers found next to the files being imported, or embedded in them. Their
+// url is the path of the image or of the audio file holding it
+pub fn fetch_local(
+    release: &crate::models::Release,
+    sources: &[PathBuf],
+    settings: &Settings,
+) -> Result<Vec<Cover>> {
+    let start = Instant::now();
+    let local = |url: &Path, (width, height): (u32, u32)| Cover {
+        provider: ArtProvider::Local,
+        url: url.to_string_lossy().to_string(),
+        thumbnail: None,
+        width: width as usize,
+        height: height as usize,
+        estimated: false,
+        title: release.title.clone(),
+        artist: release.artists.joined(),
+    };
+    let mut covers = vec![];
+    let mut folders = sources
+        .iter()
+        .filter_map(|p| p.parent().map(|p| p.to_path_buf()))
+        .collect::<Vec<_>>();
+    folders.sort();
+    folders.dedup();
+    for folder in folders.iter() {
+        for path in local_cover_files(folder, settings)? {
+            match ImageReader::open(&path)?
+                .with_guessed_format()?
+                .into_dimensions()
+            {
+                Ok(size) => covers.push(local(&path, size)),
+                Err(e) => debug!("Could not read cover {:?}: {}", path, e),
+            }
+        }
+    }
+    // Albums usually embed the same picture in all their tracks
+    let mut seen: Vec<Vec<u8>> = vec![];
+    for source in sources.iter() {
+        let picture = match TrackFile::open(source).and_then(|f| f.get_pictures()) {
+            Ok(pictures) => embedded_cover(pictures),
+            Err(e) => {
+                debug!("Could not read the pictures of {:?}: {}", source, e);
+                None
+            }
+        };
+        let picture = match picture {
+            Some(p) if !seen.contains(&p.data) => p,
+            _ => continue,
+        };
+        match ImageReader::new(Cursor::new(&picture.data))
+            .with_guessed_format()?
+            .into_dimensions()
+        {
+            Ok(size) => covers.push(local(source, size)),
+            Err(e) => debug!("Could not read the picture of {:?}: {}", source, e),
+        }
+        seen.push(picture.data);
+    }
+    trace!("Local cover search took {:?}", start.elapsed());
+    Ok(covers)
+}
+
+pub async fn search_covers(
+    release: &crate::models::Release,
+    sources: &[PathBuf],
+) -> Result<Vec<Vec<Cover>>> {
     let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
     let mut v = vec![];
     for provider in settings.art.providers.iter() {
         let res = match *provider {
             ArtProvider::CoverArtArchive => fetch_cover_art_archive(release, settings).await,
             ArtProvider::Itunes => fetch_itunes(release, settings).await,
+            ArtProvider::Deezer => fetch_deezer(release, settings).await,
+            ArtProvider::Bandcamp => fetch_bandcamp(release, settings).await,
+            ArtProvider::Discogs => fetch_discogs(release, settings).await,
+            ArtProvider::FanartTv => fetch_fanart_tv(release, settings).await,
+            ArtProvider::Local => fetch_local(release, sources, settings),
         };
         match res {
             Ok(r) => v.push(r),
@@ -142,12 +478,21 @@ pub async fn search_covers(release: &crate::models::Release) -> Result<Vec<Vec<C
     }
 }
 
-pub async fn get_cover(url: String) -> Result<(Vec<u8>, Mime)> {
+// Reads a cover found by fetch_local, either from an image or an audio file
+fn read_local(url: &str) -> Result<Vec<u8>> {
+    let path = PathBuf::from(url);
+    if TrackFormat::from_path(&path).is_err() {
+        return Ok(read(&path)?);
+    }
+    embedded_cover(TrackFile::open(&path)?.get_pictures()?)
+        .map(|p| p.data)
+        .ok_or(eyre!("The picture embedded in {:?} is gone", path))
+}
+
+async fn download(url: &str) -> Result<Vec<u8>> {
     let start = Instant::now();
-    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
     let res = CLIENT.get(url).send().await?;
-    let req_time = start.elapsed();
-    trace!("Fetch request for cover art took {:?}", req_time);
+    trace!("Fetch request for cover art took {:?}", start.elapsed());
     if !res.status().is_success() {
         bail!(
             "Fetch request for cover art returned non-success error code: {} {}",
@@ -155,35 +500,104 @@ pub async fn get_cover(url: String) -> Result<(Vec<u8>, Mime)> {
             res.text().await?
         );
     }
-    let bytes = res.bytes().await?;
-    let bytes_time = start.elapsed();
-    let img = ImageReader::new(Cursor::new(bytes))
+    Ok(res.bytes().await?.to_vec())
+}
+
+// Fetches the chosen cover. Unless the user picked it explicitly, covers
+// turning out to fail the quality checks once downloaded are dropped
+pub async fn get_cover(cover: &Cover, explicit: bool) -> Result<Option<DynamicImage>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let bytes = if cover.provider == ArtProvider::Local {
+        read_local(&cover.url)?
+    } else {
+        download(&cover.url).await?
+    };
+    let (width, height) = ImageReader::new(Cursor::new(&bytes))
         .with_guessed_format()?
-        .decode()?;
-    // .map_err(|e| eyre!(e))?;
-    trace!("Parse of cover art took {:?}", bytes_time - req_time);
-    let resized = if settings.art.width < img.width() || settings.art.height < img.height() {
-        let converted = resize(
-            &img,
-            settings.art.width,
-            settings.art.height,
-            FilterType::Gaussian,
-        );
-        let convert_time = start.elapsed();
-        trace!(
-            "Conversion of cover art took {:?} (from {}x{} to {}x{})",
-            convert_time - bytes_time - req_time,
-            img.width(),
-            img.height(),
-            converted.width(),
-            converted.height()
-        );
-        DynamicImage::ImageRgba8(converted)
+        .into_dimensions()?;
+    if let Some(issue) = quality_issue(width as usize, height as usize, &settings.art) {
+        if !explicit {
+            warn!(
+                "Not using the cover from {}, it is {}x{}: {}",
+                cover.provider, width, height, issue
+            );
+            return Ok(None);
+        }
+    }
+    decode(&bytes).map(Some)
+}
+
+// A difference hash of the image: the same picture at different sizes or
+// compression levels gets hashes differing in few bits
+fn dhash(bytes: &[u8]) -> Result<u64> {
+    let small = decode(bytes)?
+        .resize_exact(HASH_SIZE + 1, HASH_SIZE, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0;
+    for y in 0..HASH_SIZE {
+        for x in 0..HASH_SIZE {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+async fn cover_hash(cover: &Cover) -> Result<u64> {
+    let bytes = if cover.provider == ArtProvider::Local {
+        read_local(&cover.url)?
     } else {
-        img
+        download(cover.thumbnail.as_ref().unwrap_or(&cover.url)).await?
     };
-    let mut bytes: Vec<u8> = Vec::new();
-    let format: ImageOutputFormat = settings.art.format.clone().into();
-    resized.write_to(&mut Cursor::new(&mut bytes), format)?;
-    Ok((bytes, settings.art.format.mime()))
+    dhash(&bytes)
+}
+
+// Drops the candidates which look like a better ranked one, as the same
+// picture is often offered by more providers
+pub async fn dedup_covers(covers: Vec<CoverRating>) -> Result<Vec<CoverRating>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let passes = |c: &Cover| quality_issue(c.width, c.height, &settings.art).is_none();
+    let start = Instant::now();
+    let hashes = stream::iter(covers.iter().map(|CoverRating(_, c)| cover_hash(c)))
+        .buffered(PROBE_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    let mut kept: Vec<(CoverRating, Option<u64>)> = vec![];
+    for (rating, hash) in covers.into_iter().zip(hashes) {
+        let hash = match hash {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                debug!("Could not hash the cover {}: {}", rating.1.url, e);
+                None
+            }
+        };
+        let original = hash.and_then(|hash| {
+            kept.iter().position(|(_, other)| {
+                other.is_some_and(|other| (other ^ hash).count_ones() <= MAX_HASH_DISTANCE)
+            })
+        });
+        if let Some(i) = original {
+            // A copy passing the quality checks is better than any failing
+            // them, whatever their rank, and takes the place of the one it
+            // replaces
+            if passes(&rating.1) && !passes(&kept[i].0 .1) {
+                debug!(
+                    "The cover {} looks like {}, replacing it",
+                    rating.1.url, kept[i].0 .1.url
+                );
+                kept[i] = (rating, hash);
+            } else {
+                debug!(
+                    "The cover {} looks like {}, skipping it",
+                    rating.1.url, kept[i].0 .1.url
+                );
+            }
+            continue;
+        }
+        kept.push((rating, hash));
+    }
+    trace!("Cover deduplication took {:?}", start.elapsed());
+    Ok(kept.into_iter().map(|(rating, _)| rating).collect())
 }
@@ -1,4 +1,5 @@
 use eyre::{eyre, Report, Result};
+use itertools::Itertools;
 use serde_derive::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashMap;
@@ -6,7 +7,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use crate::models::GroupTracks;
-use crate::settings::ArtProvider;
+use crate::settings::{ArtProvider, ArtType};
 use crate::util::maybe_date;
 use crate::SETTINGS;
 
@@ -67,6 +68,8 @@ pub struct ReleaseGroup {
     pub disambiguation: Option<String>,
     #[serde(rename = "primary-type")]
     pub primary_type: Option<String>,
+    #[serde(rename = "secondary-types", default)]
+    pub secondary_types: Vec<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -281,6 +284,7 @@ impl From<Track> for crate::models::Track {
 
         crate::models::Track {
             mbid: Some(track.id),
+            recording_mbid: Some(track.recording.id.clone()),
             title: track.title,
             artists: track.recording.artist_credit.map_or(vec![], |artists| {
                 artists.into_iter().map(|a| a.into()).collect()
@@ -357,6 +361,11 @@ impl From<Release> for crate::models::Release {
                 .as_ref()
                 .and_then(|r| r.primary_type.as_ref())
                 .map(|pt| pt.to_lowercase()),
+            secondary_types: release
+                .release_group
+                .as_ref()
+                .map(|r| r.secondary_types.iter().map(|t| t.to_lowercase()).collect())
+                .unwrap_or_default(),
             date: SETTINGS.get().and_then(|s| {
                 if s.tagging.use_original_date {
                     original_date
@@ -418,20 +427,16 @@ impl CoverArtArchive {
             .into_iter()
             .filter_map(|i| {
                 if i.front {
-                    let sizes: HashMap<usize, String> = i
-                        .thumbnails
-                        .into_iter()
-                        .filter_map(|(k, v)| k.parse::<usize>().ok().map(|d| (d, v)))
-                        .collect();
-                    sizes.keys().max().and_then(|size| {
-                        sizes.get(size).map(|url| Cover {
-                            provider: ArtProvider::CoverArtArchive,
-                            url: url.to_string(),
-                            width: *size,
-                            height: *size,
-                            title: title.clone(),
-                            artist: artist.clone(),
-                        })
+                    let sizes = i.sizes();
+                    sizes.last().map(|(size, url)| Cover {
+                        provider: ArtProvider::CoverArtArchive,
+                        url: url.to_string(),
+                        thumbnail: sizes.first().map(|(_, url)| url.to_string()),
+                        width: *size,
+                        height: *size,
+                        estimated: false,
+                        title: title.clone(),
+                        artist: artist.clone(),
                     })
                 } else {
                     None
@@ -439,14 +444,37 @@ impl CoverArtArchive {
             })
             .collect()
     }
+
+    // The urls of the images of the given types besides the front covers,
+    // which are ranked against the other providers instead
+    pub fn art(self, types: &[ArtType]) -> Vec<(ArtType, String)> {
+        self.images
+            .into_iter()
+            .filter(|i| !i.front)
+            .filter_map(|i| {
+                let kind = i
+                    .types
+                    .iter()
+                    .filter_map(|t| ArtType::from_caa(t))
+                    .find(|t| types.contains(t))?;
+                i.sizes().pop().map(|(_, url)| (kind, url))
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cover {
     pub provider: ArtProvider,
     pub url: String,
+    // A smaller version of the image, when the provider has one
+    pub thumbnail: Option<String>,
     pub width: usize,
     pub height: usize,
+    // Whether width and height are only the largest size the provider may
+    // have, which is checked before the cover is shown or used
+    #[serde(default)]
+    pub estimated: bool,
     pub title: String,
     pub artist: String,
 }
@@ -486,24 +514,30 @@ pub struct ItunesResult {
     pub collection_name: String,
     #[serde(rename = "artworkUrl100")]
     pub artwork_url_100: String,
-    pub max_size: Option<usize>,
+}
+
+// The sizes artworks are asked for, largest first. Not all of them are
+// available for every album
+pub static ITUNES_SIZES: &[usize] = &[5000, 1200, 600];
+
+// The url of the artwork of the given size, from the 100x100 one
+pub fn itunes_artwork(artwork_url_100: &str, size: usize) -> String {
+    artwork_url_100.replace("100x100", format!("{}x{}", size, size).as_str())
 }
 
 impl From<Itunes> for Vec<Cover> {
     fn from(caa: Itunes) -> Self {
         caa.results
             .into_iter()
-            .filter_map(|i| {
-                i.max_size.map(|s| Cover {
-                    provider: ArtProvider::Itunes,
-                    url: i
-                        .artwork_url_100
-                        .replace("100x100", format!("{}x{}", s, s).as_str()),
-                    width: s,
-                    height: s,
-                    title: i.collection_name,
-                    artist: i.artist_name,
-                })
+            .map(|i| Cover {
+                provider: ArtProvider::Itunes,
+                url: itunes_artwork(&i.artwork_url_100, ITUNES_SIZES[0]),
+                thumbnail: Some(i.artwork_url_100),
+                width: ITUNES_SIZES[0],
+                height: ITUNES_SIZES[0],
+                estimated: true,
+                title: i.collection_name,
+                artist: i.artist_name,
             })
             .collect()
     }
@@ -513,9 +547,24 @@ impl From<Itunes> for Vec<Cover> {
 pub struct Image {
     approved: bool,
     front: bool,
+    #[serde(default)]
+    types: Vec<String>,
     thumbnails: HashMap<String, String>,
 }
 
+impl Image {
+    // The sized thumbnails of the image, smallest first
+    fn sizes(&self) -> Vec<(usize, String)> {
+        let mut sizes = self
+            .thumbnails
+            .iter()
+            .filter_map(|(k, v)| k.parse::<usize>().ok().map(|d| (d, v.to_string())))
+            .collect::<Vec<_>>();
+        sizes.sort();
+        sizes
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Thumbnails {
     #[serde(rename = "250")]
@@ -527,3 +576,197 @@ pub struct Thumbnails {
     large: String,
     small: String,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deezer {
+    pub data: Vec<DeezerAlbum>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeezerAlbum {
+    pub title: String,
+    pub cover_medium: Option<String>,
+    pub cover_xl: Option<String>,
+    pub artist: DeezerArtist,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeezerArtist {
+    pub name: String,
+}
+
+// Side of the cover_xl images
+static DEEZER_SIZE: usize = 1000;
+
+impl From<Deezer> for Vec<Cover> {
+    fn from(deezer: Deezer) -> Self {
+        deezer
+            .data
+            .into_iter()
+            .filter_map(|a| {
+                a.cover_xl.map(|url| Cover {
+                    provider: ArtProvider::Deezer,
+                    url,
+                    thumbnail: a.cover_medium,
+                    width: DEEZER_SIZE,
+                    height: DEEZER_SIZE,
+                    estimated: false,
+                    title: a.title,
+                    artist: a.artist.name,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandcampSearch {
+    pub search_text: String,
+    // "a" limits the results to albums
+    pub search_filter: String,
+    pub full_page: bool,
+    pub fan_id: Option<u64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bandcamp {
+    pub auto: BandcampResults,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandcampResults {
+    pub results: Vec<BandcampResult>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandcampResult {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+    pub band_name: Option<String>,
+    pub img: Option<String>,
+}
+
+// Images are served in a few formats, chosen by the suffix of their name.
+// The 10th is the 1200x1200 one
+static BANDCAMP_FORMAT: &str = "_10.jpg";
+static BANDCAMP_SIZE: usize = 1200;
+
+impl From<Bandcamp> for Vec<Cover> {
+    fn from(bandcamp: Bandcamp) -> Self {
+        bandcamp
+            .auto
+            .results
+            .into_iter()
+            .filter(|r| r.kind == "a")
+            .filter_map(|r| {
+                let img = r.img?;
+                let (base, _) = img.rsplit_once('_')?;
+                Some(Cover {
+                    provider: ArtProvider::Bandcamp,
+                    url: format!("{}{}", base, BANDCAMP_FORMAT),
+                    thumbnail: Some(img.clone()),
+                    width: BANDCAMP_SIZE,
+                    height: BANDCAMP_SIZE,
+                    estimated: false,
+                    title: r.name,
+                    artist: r.band_name.unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscogsSearch {
+    pub results: Vec<DiscogsResult>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscogsResult {
+    pub id: u64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscogsRelease {
+    pub title: String,
+    #[serde(default)]
+    pub artists: Vec<DiscogsArtist>,
+    #[serde(default)]
+    pub images: Vec<DiscogsImage>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscogsArtist {
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscogsImage {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub uri: String,
+    pub uri150: Option<String>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl From<DiscogsRelease> for Vec<Cover> {
+    fn from(release: DiscogsRelease) -> Self {
+        let artist = release.artists.into_iter().map(|a| a.name).join(", ");
+        release
+            .images
+            .into_iter()
+            .filter(|i| i.kind == "primary")
+            .map(|i| Cover {
+                provider: ArtProvider::Discogs,
+                url: i.uri,
+                thumbnail: i.uri150,
+                width: i.width,
+                height: i.height,
+                estimated: false,
+                title: release.title.clone(),
+                artist: artist.clone(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FanartTv {
+    #[serde(default)]
+    pub albums: HashMap<String, FanartTvAlbum>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FanartTvAlbum {
+    #[serde(default)]
+    pub albumcover: Vec<FanartTvImage>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FanartTvImage {
+    pub url: String,
+}
+
+// fanart.tv only accepts 1000x1000 album covers
+static FANART_TV_SIZE: usize = 1000;
+
+impl FanartTv {
+    pub fn into(self, title: String, artist: String) -> Vec<Cover> {
+        self.albums
+            .into_values()
+            .flat_map(|a| a.albumcover)
+            .map(|i| Cover {
+                provider: ArtProvider::FanartTv,
+                thumbnail: Some(i.url.replace("/fanart/", "/preview/")),
+                url: i.url,
+                width: FANART_TV_SIZE,
+                height: FANART_TV_SIZE,
+                estimated: false,
+                title: title.clone(),
+                artist: artist.clone(),
+            })
+            .collect()
+    }
+}
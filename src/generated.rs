@@ -1 +1 @@
-pub use sqlx_migrate :: prelude :: * ; # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20220910124957."] pub mod _1_init_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20220910124957."] pub mod _1_init_revert { } # [doc = r" All the migrations."] pub fn migrations () -> impl IntoIterator < Item = Migration < sqlx :: Sqlite >> { [sqlx_migrate :: Migration :: new ("init" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20220910124957_init.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20220910124957_init.revert.sql")) . await ? ; Ok (()) })) ,] }
\ No newline at end of file
+pub use sqlx_migrate :: prelude :: * ; # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20220910124957."] pub mod _1_init_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20220910124957."] pub mod _1_init_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221016183000."] pub mod _2_imports_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221016183000."] pub mod _2_imports_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221018201500."] pub mod _3_import_hashes_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221018201500."] pub mod _3_import_hashes_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221019094500."] pub mod _4_recordings_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221019094500."] pub mod _4_recordings_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221020110000."] pub mod _5_secondary_types_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221020110000."] pub mod _5_secondary_types_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221021093000."] pub mod _6_extras_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221021093000."] pub mod _6_extras_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221022100000."] pub mod _7_import_pictures_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221022100000."] pub mod _7_import_pictures_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221023100000."] pub mod _8_copies_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221023100000."] pub mod _8_copies_revert { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221024100000."] pub mod _9_credit_order_migrate { } # [allow (dead_code)] # [allow (clippy :: all , clippy :: pedantic)] # [doc = "Created at 20221024100000."] pub mod _9_credit_order_revert { } # [doc = r" All the migrations."] pub fn migrations () -> impl IntoIterator < Item = Migration < sqlx :: Sqlite >> { [sqlx_migrate :: Migration :: new ("init" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20220910124957_init.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20220910124957_init.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("imports" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221016183000_imports.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221016183000_imports.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("import_hashes" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221018201500_import_hashes.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221018201500_import_hashes.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("recordings" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221019094500_recordings.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221019094500_recordings.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("secondary_types" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221020110000_secondary_types.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221020110000_secondary_types.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("extras" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221021093000_extras.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221021093000_extras.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("import_pictures" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221022100000_import_pictures.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221022100000_import_pictures.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("copies" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221023100000_copies.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221023100000_copies.revert.sql")) . await ? ; Ok (()) })) , sqlx_migrate :: Migration :: new ("credit_order" , | ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221024100000_credit_order.migrate.sql")) . await ? ; Ok (()) })) . reversible (| ctx | std :: boxed :: Box :: pin (async move { use sqlx :: Executor ; let mut ctx : sqlx_migrate :: prelude :: MigrationContext < sqlx :: Sqlite > = ctx ; ctx . tx () . execute (include_str ! ("/home/luca/github/tagger/migrations/20221024100000_credit_order.revert.sql")) . await ? ; Ok (()) })) ,] }
\ No newline at end of file
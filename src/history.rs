@@ -0,0 +1,183 @@
+use crate::library::{copies_of, prune, Delete, Fetch, Filter, Store};
+use crate::models::{Extra, Format, Import, ImportedFile, Release, Track, TrackCopy};
+use crate::remove::clean_folder;
+use crate::settings::ImportMode;
+use crate::theme::DialoguerTheme;
+use crate::track::file::{relocate_file, TrackFile};
+use crate::{DB, SETTINGS};
+use chrono::Utc;
+use dialoguer::Confirm;
+use eyre::{bail, eyre, Result};
+use log::{info, trace, warn};
+use std::fs::remove_file;
+use std::time::Instant;
+
+static FMT_RELEASE: &str = "{album_artist} - {album}";
+
+pub async fn history() -> Result<()> {
+    let start = Instant::now();
+    let imports =
+        Import::filter::<String, String>(vec![], vec![" ORDER BY id".to_string()]).await?;
+    for import in imports.iter() {
+        let release = match import.release.as_ref() {
+            Some(mbid) => match Release::fetch(mbid.to_string()).await {
+                Ok(release) => release.fmt(FMT_RELEASE)?,
+                Err(_) => mbid.to_string(),
+            },
+            None => "(unknown release)".to_string(),
+        };
+        println!(
+            "{}\t{}\t{}\t{} ({} files){}",
+            import.id.unwrap_or_default(),
+            import.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            import.mode,
+            release,
+            import.files.len(),
+            if import.undone.is_some() {
+                " [undone]"
+            } else {
+                ""
+            }
+        );
+    }
+    info!("Took {:?}", start.elapsed());
+    Ok(())
+}
+
+// Restores the tags and pictures a source file had before being imported.
+// Companion files have no tags to restore
+fn restore(file: &ImportedFile) -> Result<()> {
+    if file.track.is_none() {
+        return Ok(());
+    }
+    let mut track = TrackFile::open(&file.source)?;
+    track.set_all(file.original_tags.clone())?;
+    track.set_pictures(file.original_pictures.clone())?;
+    track.write()
+}
+
+fn undo_file(file: &ImportedFile, mode: ImportMode) -> Result<()> {
+    trace!("Undoing the import of {:?}", file.source);
+    match mode {
+        ImportMode::Copy | ImportMode::Reflink => remove_file(&file.destination)?,
+        // Links share their content with the source, which got retagged too
+        ImportMode::Hardlink | ImportMode::Symlink => {
+            remove_file(&file.destination)?;
+            restore(file)?;
+        }
+        ImportMode::Move if file.track.is_none() => {
+            relocate_file(&file.destination, &file.source, ImportMode::Move)?
+        }
+        ImportMode::Move => {
+            TrackFile::open(&file.destination)?.relocate(&file.source, ImportMode::Move)?;
+            restore(file)?;
+        }
+        ImportMode::InPlace => restore(file)?,
+    }
+    Ok(())
+}
+
+pub async fn undo(id: i64) -> Result<()> {
+    let start = Instant::now();
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut import = Import::filter(vec![("id", id)], vec![])
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(eyre!("No import with id {}", id))?;
+    if import.undone.is_some() {
+        bail!("Import {} has already been undone", id);
+    }
+    let prompt = match import.mode {
+        ImportMode::Move => format!("Move {} files back to their source?", import.files.len()),
+        ImportMode::InPlace => format!("Restore the tags of {} files?", import.files.len()),
+        _ => format!("Delete {} imported files?", import.files.len()),
+    };
+    if !Confirm::with_theme(&DialoguerTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()?
+    {
+        info!("Nothing undone");
+        return Ok(());
+    }
+
+    // Only drop the tracks which still point to the imported files, they may
+    // have been imported again since
+    let mut tracks = vec![];
+    for file in import.files.iter() {
+        if let Some(mbid) = file.track.as_ref() {
+            tracks.extend(
+                Track::filter(vec![("tracks.mbid", mbid)], vec![])
+                    .await?
+                    .into_iter()
+                    .filter(|t| t.path.as_ref() == Some(&file.destination)),
+            );
+        }
+    }
+    let copies = copies_of(&tracks).await?;
+    for file in import.files.iter().rev() {
+        if let Err(e) = undo_file(file, import.mode) {
+            warn!("Could not undo the import of {:?}: {}", file.source, e);
+        }
+    }
+
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    for track in tracks.iter() {
+        track.delete(&mut tx).await?;
+        // The first copy left takes the place of the undone track
+        let mut left = copies.iter().filter(|c| c.track == track.mbid);
+        if let Some(first) = left.next() {
+            Track {
+                path: Some(first.path.clone()),
+                format: first.format,
+                ..track.clone()
+            }
+            .store(&mut tx)
+            .await?;
+            for copy in left {
+                copy.store(&mut tx).await?;
+            }
+        }
+    }
+    // Imports which kept both albums brought in copies rather than tracks
+    for file in import.files.iter().filter(|f| f.track.is_some()) {
+        TrackCopy {
+            path: file.destination.clone(),
+            track: file.track.clone(),
+            format: None,
+        }
+        .delete(&mut tx)
+        .await?;
+    }
+    for file in import.files.iter().filter(|f| f.track.is_none()) {
+        Extra {
+            path: file.destination.clone(),
+            release: import.release.clone(),
+        }
+        .delete(&mut tx)
+        .await?;
+    }
+    prune(&mut tx).await?;
+    import.undone = Some(Utc::now().naive_utc());
+    import.store(&mut tx).await?;
+    tx.commit().await?;
+
+    if import.mode != ImportMode::InPlace {
+        let mut folders = import
+            .files
+            .iter()
+            .filter_map(|f| f.destination.parent().map(|p| p.to_path_buf()))
+            .collect::<Vec<_>>();
+        folders.sort();
+        folders.dedup();
+        for folder in folders.iter() {
+            if let Err(e) = clean_folder(folder, &settings.library) {
+                warn!("Could not clean up folder {:?}: {}", folder, e);
+            }
+        }
+    }
+    info!("Undid import {}, took {:?}", id, start.elapsed());
+    Ok(())
+}
@@ -1,25 +1,40 @@
-use dialoguer::{Confirm, Input, Select};
+use chrono::Utc;
+use dialoguer::{Input, Select};
 use eyre::{bail, eyre, Context, Result};
-use log::{debug, info, warn};
+use globset::{Glob, GlobSetBuilder};
+use itertools::Itertools;
+use log::{debug, error, info, warn};
 use scan_dir::ScanDir;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{QueryBuilder, Row};
 use std::cmp::Ordering;
-use std::fs::canonicalize;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::{canonicalize, remove_file, rename};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Instant;
 
-use crate::fetch::cover::{get_cover, search_covers};
-use crate::fetch::structures::Cover;
+use crate::art::{embedded, find_art, save_art};
+use crate::collision::resolve;
 use crate::fetch::{get, search};
 use crate::library::LibraryTrack;
-use crate::library::Store;
-use crate::models::{Artists, GroupTracks, Release, Track};
-use crate::rank::CoverRating;
-use crate::rank::{match_tracks, rank_covers};
+use crate::library::{copies_of, prune, Delete, Filter, Store};
+use crate::models::{
+    Artists, Extra, Format, GroupTracks, Import, ImportedFile, Release, Track, TrackCopy,
+};
+use crate::rank::match_tracks;
+use crate::remove::clean_folder;
+use crate::sanitize::Sanitizer;
+use crate::settings::{Collision, ImportMode};
+use crate::template::Template;
 use crate::theme::DialoguerTheme;
-use crate::track::file::TrackFile;
-use crate::track::picture::{write_picture, Picture, PictureType};
-use crate::util::{mkdirp, path_to_str};
-use crate::SETTINGS;
+use crate::track::diff::report;
+use crate::track::file::{relocate_file, TrackFile};
+use crate::track::picture::Picture;
+use crate::util::{hash_file, mkdirp, path_to_str};
+use crate::{DB, SETTINGS};
+
+static FMT: &str = "{album_artist} - {album} - {track_title}";
 
 fn all_files(path: &PathBuf) -> Result<Vec<PathBuf>> {
     ScanDir::files()
@@ -70,47 +85,428 @@ async fn ask(
     }
 }
 
-fn ask_cover(theme: &DialoguerTheme, covers: Vec<CoverRating>) -> Option<Cover> {
-    let CoverRating(match_rank, mut cover) = covers.first()?.clone();
-    let mut index: usize = 0;
-    info!(
-        "Using cover art for release {} - {} from {} ({}x{}, diff: {})",
-        cover.artist, cover.title, cover.provider, cover.width, cover.height, match_rank
-    );
-    let covers_strs: Vec<String> = covers
-        .iter()
-        .map(|CoverRating(r, c)| {
-            format!(
-                "{}x{} for release {} - {} from {} (diff: {})",
-                c.width, c.height, c.artist, c.title, c.provider, r
-            )
-        })
+// Brings a single file into the library and tags it. The file is added to
+// `placed` as soon as it has been relocated, so that it can be rolled back
+fn place(
+    src: &mut TrackFile,
+    dest: &Track,
+    mode: ImportMode,
+    pictures: &[Picture],
+    placed: &mut Vec<(TrackFile, TrackFile)>,
+) -> Result<()> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let path = dest
+        .path
+        .as_ref()
+        .ok_or(eyre!("The track doesn't have an associated path"))?;
+    let original = src.clone();
+    src.relocate(path, mode).wrap_err(eyre!(
+        "Could not {} track {:?} to its new location: {:?}",
+        mode,
+        src.path,
+        path
+    ))?;
+    placed.push((original, src.clone()));
+    src.retag(&settings.tagging, dest.clone().try_into()?)
+        .wrap_err(eyre!("Could not apply new tags to track: {:?}", path))?;
+    if !pictures.is_empty() {
+        src.set_pictures(pictures.to_vec())?;
+    }
+    src.write()
+        .wrap_err(eyre!("Could not write tags to track: {:?}", path))
+}
+
+// The deepest folder containing all the given paths
+fn common_folder(paths: &[PathBuf]) -> Option<PathBuf> {
+    let mut folder = paths.first()?.parent()?.to_path_buf();
+    while !paths.iter().all(|p| p.starts_with(&folder)) {
+        if !folder.pop() {
+            return None;
+        }
+    }
+    Some(folder)
+}
+
+// The non-audio files of the imported folder matching the import.extras
+// patterns, along with where they belong inside the album folder
+fn plan_extras(
+    source: &Path,
+    files: &[&PathBuf],
+    release: &Release,
+    album: &Path,
+    mode: ImportMode,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut builder = GlobSetBuilder::new();
+    for extra in settings.import.extras.iter() {
+        builder.add(Glob::new(extra.glob())?);
+    }
+    let globs = builder.build()?;
+    let sanitizer = Sanitizer::new(&settings.paths)?;
+    let vars: HashMap<String, Vec<String>> = release
+        .vars()?
+        .into_iter()
+        .map(|(k, v)| (k, v.iter().map(|s| sanitizer.value(s)).collect()))
         .collect();
-    loop {
-        if Confirm::with_theme(theme)
-            .with_prompt("Proceed?")
-            .interact()
-            .ok()?
+    let mut extras = vec![];
+    for file in files.iter() {
+        let relative = file.strip_prefix(source)?;
+        let pattern = match globs.matches(relative).into_iter().min() {
+            Some(i) => &settings.import.extras[i],
+            None => continue,
+        };
+        if mode == ImportMode::InPlace {
+            extras.push((file.to_path_buf(), file.to_path_buf()));
+            continue;
+        }
+        let mut vars = vars.clone();
+        vars.insert(
+            "filename".to_string(),
+            file.file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .into_iter()
+                .collect(),
+        );
+        vars.insert(
+            "relative_path".to_string(),
+            vec![relative.to_string_lossy().to_string()],
+        );
+        let rendered = Template::from_str(pattern.path())?.render(&vars);
+        if rendered.is_empty() {
+            warn!("Not importing {:?}, its path template is empty", file);
+            continue;
+        }
+        extras.push((file.to_path_buf(), album.join(rendered)));
+    }
+    Ok(extras)
+}
+
+// Brings an extra file into the album folder. Existing files, like the
+// cover written just before, are never overwritten
+fn place_extra(
+    src: &Path,
+    dest: &Path,
+    mode: ImportMode,
+    placed: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    if mode != ImportMode::InPlace {
+        if dest.exists() {
+            warn!("Not importing {:?}, {:?} already exists", src, dest);
+            return Ok(());
+        }
+        if let Some(parent) = dest.parent() {
+            mkdirp(parent)?;
+        }
+        relocate_file(src, dest, mode).wrap_err(eyre!(
+            "Could not {} file {:?} to its new location: {:?}",
+            mode,
+            src,
+            dest
+        ))?;
+    }
+    placed.push((src.to_path_buf(), dest.to_path_buf()));
+    Ok(())
+}
+
+fn unplace_extra(src: &Path, dest: &Path, mode: ImportMode) -> Result<()> {
+    match mode {
+        ImportMode::InPlace => {}
+        ImportMode::Move => relocate_file(dest, src, ImportMode::Move)?,
+        _ => remove_file(dest)?,
+    }
+    Ok(())
+}
+
+// Undoes the placement of a file, leaving the source as it was before the
+// import. Links share their content with the source, so its tags are
+// restored as well
+fn unplace(original: &TrackFile, placed: &mut TrackFile, mode: ImportMode) -> Result<()> {
+    match mode {
+        ImportMode::Copy | ImportMode::Reflink => remove_file(&placed.path)?,
+        ImportMode::Hardlink | ImportMode::Symlink => {
+            remove_file(&placed.path)?;
+            original.clone().write()?;
+        }
+        ImportMode::Move => {
+            placed.relocate(&original.path, ImportMode::Move)?;
+            original.clone().write()?;
+        }
+        ImportMode::InPlace => original.clone().write()?,
+    }
+    Ok(())
+}
+
+fn rollback(
+    placed: Vec<(TrackFile, TrackFile)>,
+    placed_extras: Vec<(PathBuf, PathBuf)>,
+    stashed: Vec<(PathBuf, PathBuf)>,
+    mode: ImportMode,
+) {
+    let settings = match SETTINGS.get() {
+        Some(s) => s,
+        None => {
+            error!("Could not read settings, skipping rollback");
+            return;
+        }
+    };
+    let mut folders = vec![];
+    for (src, dest) in placed_extras.into_iter().rev() {
+        if let Err(e) = unplace_extra(&src, &dest, mode) {
+            error!("Could not roll back file {:?}: {}", src, e);
+        }
+        if let Some(parent) = dest.parent() {
+            folders.push(parent.to_path_buf());
+        }
+    }
+    for (original, mut file) in placed.into_iter().rev() {
+        if let Err(e) = unplace(&original, &mut file, mode) {
+            error!("Could not roll back track {:?}: {}", original.path, e);
+        }
+        if let Some(parent) = file.path.parent() {
+            folders.push(parent.to_path_buf());
+        }
+    }
+    unstash(stashed);
+    if mode == ImportMode::InPlace {
+        return;
+    }
+    folders.sort();
+    folders.dedup();
+    for folder in folders.iter() {
+        if let Err(e) = clean_folder(folder, &settings.library) {
+            warn!("Could not clean up folder {:?}: {}", folder, e);
+        }
+    }
+}
+
+// Stores the whole album, along with its extra files and its entry in the
+// import journal, in a single transaction. The replaced tracks are dropped
+// from the library in the same transaction, while the files of the kept ones
+// are tracked as copies
+async fn store_tracks(
+    tracks: &[(TrackFile, Track)],
+    extras: &[Extra],
+    journal: &Import,
+    replaced: &[Track],
+    replaced_imports: &[Import],
+    kept: &[Track],
+) -> Result<()> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    if !replaced.is_empty() {
+        for track in replaced.iter() {
+            track.delete(&mut tx).await?;
+        }
+        prune(&mut tx).await?;
+        for import in replaced_imports.iter() {
+            import.store(&mut tx).await?;
+        }
+    }
+    for (_, track) in tracks.iter() {
+        if kept
+            .iter()
+            .any(|k| k.mbid == track.mbid && k.path != track.path)
         {
-            break;
+            TrackCopy {
+                path: track
+                    .path
+                    .clone()
+                    .ok_or(eyre!("The track doesn't have an associated path"))?,
+                track: track.mbid.clone(),
+                format: track.format,
+            }
+            .store(&mut tx)
+            .await?;
+        } else {
+            track.store(&mut tx).await?;
         }
+    }
+    for extra in extras.iter() {
+        extra.store(&mut tx).await?;
+    }
+    journal.store(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+enum Duplicate {
+    Skip,
+    Replace,
+    KeepBoth,
+}
+
+fn ask_duplicate(theme: &DialoguerTheme, existing: &[Track]) -> Result<Duplicate> {
+    warn!(
+        "{} of these tracks are already in the library:",
+        existing.len()
+    );
+    for track in existing.iter() {
+        println!("{} ({:?})", track.fmt(FMT)?, track.path);
+    }
+    // When keeping both, the existing tracks stay as they are and the new
+    // files are tracked as copies of them
+    let choice = Select::with_theme(theme)
+        .with_prompt("What should be done?")
+        .items(&[
+            "Skip this import",
+            "Replace the existing album",
+            "Keep both",
+        ])
+        .default(0)
+        .interact()?;
+    Ok(match choice {
+        0 => Duplicate::Skip,
+        1 => Duplicate::Replace,
+        _ => Duplicate::KeepBoth,
+    })
+}
+
+// Whether the given directory has already been imported (and not undone)
+async fn seen_before(path: &PathBuf) -> Result<bool> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let count: i64 =
+        sqlx::query("SELECT COUNT(*) AS count FROM imports WHERE source = ? AND undone IS NULL")
+            .bind(path_to_str(path)?)
+            .fetch_one(db)
+            .await?
+            .try_get("count")?;
+    Ok(count > 0)
+}
 
-        index = Select::with_theme(theme)
-            .items(&covers_strs)
-            .default(index)
-            .interact()
-            .ok()?;
-        cover = covers[index].1.clone();
+async fn tracks_by_mbid(mbids: Vec<String>) -> Result<Vec<Track>> {
+    let mut tracks = vec![];
+    for mbid in mbids.into_iter() {
+        tracks.extend(Track::filter(vec![("tracks.mbid", mbid)], vec![]).await?);
     }
-    Some(cover)
+    Ok(tracks)
 }
 
-pub async fn import(path: &PathBuf) -> Result<()> {
+// Library tracks coming from previous imports of the given files, matched
+// either by path or by content
+async fn already_imported(paths: &[PathBuf], hashes: &[String]) -> Result<Vec<Track>> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let paths = paths.iter().map(path_to_str).collect::<Result<Vec<_>>>()?;
+    let mut qb = QueryBuilder::new(
+        "SELECT DISTINCT import_files.track AS track FROM import_files INNER JOIN imports ON imports.id = import_files.import WHERE imports.undone IS NULL AND import_files.track IS NOT NULL AND (import_files.hash IN (",
+    );
+    let mut separated = qb.separated(", ");
+    for hash in hashes.iter() {
+        separated.push_bind(hash);
+    }
+    qb.push(") OR import_files.source IN (");
+    let mut separated = qb.separated(", ");
+    for path in paths.iter() {
+        separated.push_bind(path);
+    }
+    qb.push(") OR import_files.destination IN (");
+    let mut separated = qb.separated(", ");
+    for path in paths.iter() {
+        separated.push_bind(path);
+    }
+    qb.push("))");
+    let mbids = qb
+        .build()
+        .try_map(|row: SqliteRow| row.try_get("track"))
+        .fetch_all(db)
+        .await?;
+    tracks_by_mbid(mbids).await
+}
+
+// The journal entries of the releases the given tracks belong to, which are
+// marked as undone once they are replaced
+async fn replaced_imports(existing: &[Track]) -> Result<Vec<Import>> {
+    let releases = existing
+        .iter()
+        .filter_map(|t| t.release.as_ref().and_then(|r| r.mbid.clone()))
+        .unique()
+        .collect::<Vec<_>>();
+    let mut imports = vec![];
+    for release in releases.into_iter() {
+        imports
+            .extend(Import::filter(vec![("release", release)], vec![" AND undone IS NULL"]).await?);
+    }
+    for import in imports.iter_mut() {
+        import.undone = Some(Utc::now().naive_utc());
+    }
+    Ok(imports)
+}
+
+// Moves the files of the replaced tracks out of the way of the new ones,
+// keeping them next to their original path until the import succeeds. Files
+// are only touched when the library owns them
+fn stash(existing: &[PathBuf], sources: &[PathBuf]) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut stashed = vec![];
+    for path in existing.iter() {
+        if !path.starts_with(&settings.library) || sources.contains(path) || !path.exists() {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .ok_or(eyre!("Invalid file name: {:?}", path))?
+            .to_string_lossy();
+        let tmp = path.with_file_name(format!(".{}.tagger-replaced", name));
+        if let Err(e) = rename(path, &tmp) {
+            unstash(stashed);
+            return Err(eyre!(e)).wrap_err(eyre!("Could not move replaced track {:?}", path));
+        }
+        stashed.push((path.clone(), tmp));
+    }
+    Ok(stashed)
+}
+
+fn unstash(stashed: Vec<(PathBuf, PathBuf)>) {
+    for (path, tmp) in stashed.into_iter().rev() {
+        if let Err(e) = rename(&tmp, &path) {
+            error!("Could not restore replaced track {:?}: {}", path, e);
+        }
+    }
+}
+
+// Deletes the files of the replaced tracks, once the new ones are tracked
+fn delete_replaced(stashed: Vec<(PathBuf, PathBuf)>) {
+    let settings = match SETTINGS.get() {
+        Some(s) => s,
+        None => {
+            error!("Could not read settings, keeping the replaced files");
+            return;
+        }
+    };
+    let mut folders = vec![];
+    for (path, tmp) in stashed.into_iter() {
+        warn!("Deleting replaced track {:?}", path);
+        if let Err(e) = remove_file(&tmp) {
+            warn!("Could not delete file {:?}: {}", tmp, e);
+        }
+        if let Some(parent) = path.parent() {
+            folders.push(parent.to_path_buf());
+        }
+    }
+    folders.sort();
+    folders.dedup();
+    for folder in folders.iter() {
+        if let Err(e) = clean_folder(folder, &settings.library) {
+            warn!("Could not clean up folder {:?}: {}", folder, e);
+        }
+    }
+}
+
+pub async fn import(
+    path: &PathBuf,
+    mode: ImportMode,
+    incremental: bool,
+    dry_run: bool,
+) -> Result<()> {
     let start = Instant::now();
     let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
     let theme = DialoguerTheme::default();
 
-    let files = all_files(&canonicalize(path)?)?;
+    let source = canonicalize(path)?;
+    if incremental && seen_before(&source).await? {
+        info!("Skipping {:?}, it has already been imported", path);
+        return Ok(());
+    }
+    let files = all_files(&source)?;
     let (tracks, errors): (Vec<_>, Vec<_>) =
         files.iter().map(TrackFile::open).partition(Result::is_ok);
     let tracks: Vec<_> = tracks.into_iter().map(Result::unwrap).collect();
@@ -126,6 +522,23 @@ pub async fn import(path: &PathBuf) -> Result<()> {
     if tracks.is_empty() {
         bail!("No tracks to import were found");
     }
+    let sources = tracks.iter().map(|t| t.path.clone()).collect::<Vec<_>>();
+    let hashes = sources.iter().map(hash_file).collect::<Result<Vec<_>>>()?;
+    let mut asked = false;
+    let mut replaced = vec![];
+    let mut kept = vec![];
+    let previous = already_imported(&sources, &hashes).await?;
+    if !previous.is_empty() {
+        asked = true;
+        match ask_duplicate(&theme, &previous)? {
+            Duplicate::Skip => {
+                info!("Skipping {:?}", path);
+                return Ok(());
+            }
+            Duplicate::Replace => replaced = previous,
+            Duplicate::KeepBoth => kept = previous,
+        }
+    }
     let (choice_release, choice_tracks) = tracks
         .clone()
         .group_tracks()
@@ -174,10 +587,6 @@ pub async fn import(path: &PathBuf) -> Result<()> {
         .await?;
     }
 
-    let covers_by_provider = search_covers(&final_release).await?;
-    let covers = rank_covers(covers_by_provider, &final_release);
-    let maybe_cover = ask_cover(&theme, covers);
-    let mut maybe_picture: Option<Picture> = None;
     let mut final_tracks = tracks_map
         .iter()
         .enumerate()
@@ -185,13 +594,129 @@ pub async fn import(path: &PathBuf) -> Result<()> {
         .collect::<Vec<_>>();
     for (src, dest) in final_tracks.iter_mut() {
         dest.format = Some(src.format);
-        let dest_path = dest.path()?;
-        dest.path = Some(dest_path.clone());
+        dest.path = Some(match mode {
+            ImportMode::InPlace => src.path.clone(),
+            _ => dest.path()?,
+        });
+    }
+    if let Some(release_group) = final_release.release_group_mbid.as_ref() {
+        for edition in Release::filter(vec![("release_group_mbid", release_group)], vec![])
+            .await?
+            .into_iter()
+            .filter(|r| r.mbid != final_release.mbid)
+        {
+            warn!(
+                "Another edition of this release is already in the library: {}",
+                edition.fmt("{album_artist} - {album} ({release_year})")?
+            );
+        }
     }
+    // The files may be new while the release is already in the library
+    if !asked {
+        let existing = tracks_by_mbid(
+            final_tracks
+                .iter()
+                .filter_map(|(_, t)| t.mbid.clone())
+                .collect(),
+        )
+        .await?;
+        if !existing.is_empty() {
+            match ask_duplicate(&theme, &existing)? {
+                Duplicate::Skip => {
+                    info!("Skipping {:?}", path);
+                    return Ok(());
+                }
+                Duplicate::Replace => replaced = existing,
+                Duplicate::KeepBoth => kept = existing,
+            }
+        }
+    }
+    // The files of the replaced tracks, and their copies, make room for the
+    // new ones, as long as replace is going to delete them
+    let vacated = replaced
+        .iter()
+        .filter_map(|t| t.path.clone())
+        .chain(copies_of(&replaced).await?.into_iter().map(|c| c.path))
+        .filter(|p| p.starts_with(&settings.library) && !sources.contains(p))
+        .collect::<Vec<_>>();
+    let mut batch = final_tracks
+        .iter()
+        .map(|(src, dest)| {
+            Ok((
+                Some(src.path.clone()),
+                dest.path
+                    .clone()
+                    .ok_or(eyre!("The track doesn't have an associated path"))?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let album = common_folder(&batch.iter().map(|(_, d)| d.clone()).collect::<Vec<_>>())
+        .ok_or(eyre!("Could not find the album folder"))?;
+    let others = files
+        .iter()
+        .filter(|f| !sources.contains(f))
+        .collect::<Vec<_>>();
+    let extra_files = plan_extras(&source, &others, &final_release, &album, mode)?;
+    batch.extend(
+        extra_files
+            .iter()
+            .map(|(src, dest)| (Some(src.clone()), dest.clone())),
+    );
+    // Both copies have to fit in the library, the new one is renamed if need be
+    let strategy = if kept.is_empty() {
+        settings.paths.collisions
+    } else {
+        Collision::Rename
+    };
+    let mut targets = resolve(&batch, &vacated, strategy)?;
+    let extra_targets = targets.split_off(final_tracks.len());
+    let mut final_tracks = final_tracks
+        .into_iter()
+        .zip(targets)
+        .filter_map(|((src, mut dest), target)| {
+            dest.path = Some(target?);
+            Some((src, dest))
+        })
+        .collect::<Vec<_>>();
+    let extra_files = extra_files
+        .into_iter()
+        .zip(extra_targets)
+        .filter_map(|((src, _), target)| Some((src, target?)))
+        .collect::<Vec<_>>();
+    if final_tracks.is_empty() {
+        bail!("All tracks have been skipped");
+    }
+    if dry_run {
+        if !replaced.is_empty() {
+            info!("Would replace {} tracks", replaced.len());
+        }
+        if !kept.is_empty() {
+            info!("Would keep a copy of {} tracks", kept.len());
+        }
+        for (src, dest) in extra_files.iter().filter(|(src, dest)| src != dest) {
+            info!("Would import {:?} to {:?}", src, dest);
+        }
+        for (src, dest) in final_tracks.iter() {
+            let path = dest
+                .path
+                .as_ref()
+                .ok_or(eyre!("The track doesn't have an associated path"))?;
+            let mut retagged = src.clone();
+            retagged.retag(&settings.tagging, dest.clone().try_into()?)?;
+            report(&src.path, path, &src.diff(&retagged));
+        }
+        info!("Dry run done, took {:?}", start.elapsed());
+        return Ok(());
+    }
+
+    let replaced_imports = replaced_imports(&replaced).await?;
+    let art = find_art(&final_release, &sources, true).await?;
     let mut folders = final_tracks
         .iter()
         .map(|(_, t)| {
-            Ok(t.path()?
+            Ok(t.path
+                .as_ref()
+                .ok_or(eyre!("The track doesn't have an associated path"))?
                 .parent()
                 .ok_or(eyre!("Could not get parent"))?
                 .to_path_buf())
@@ -202,46 +727,88 @@ pub async fn import(path: &PathBuf) -> Result<()> {
     for dest in folders.iter() {
         mkdirp(dest)?;
     }
-    if let Some(cover) = maybe_cover {
-        let (image, mime) = get_cover(cover.url).await?;
-        let picture = Picture {
-            mime_type: mime,
-            picture_type: PictureType::CoverFront,
-            description: "Front".to_string(),
-            data: image,
-        };
-        for dest in folders.into_iter() {
-            write_picture(&picture, &dest)?;
-        }
-        maybe_picture = Some(picture)
-    } else {
-        warn!("No album art found")
-    }
+    let mut extras = save_art(&art, &folders, &final_release.mbid)?;
+    let pictures = embedded(&art)?;
+    // The replaced album is only dropped once the new one is in place
+    let stashed = stash(&vacated, &sources).wrap_err("Could not replace the existing album")?;
+    let mut placed: Vec<(TrackFile, TrackFile)> = vec![];
     for (src, dest) in final_tracks.iter_mut() {
         debug!("Beofre tagging {:?}", src);
-        let path = dest
-            .path
-            .as_ref()
-            .ok_or(eyre!("The track doesn't have an associated path"))?;
-        src.duplicate_to(path).wrap_err(eyre!(
-            "Could not copy track {:?} to its new location: {:?}",
-            src.path,
-            path
-        ))?;
-        if settings.tagging.clear {
-            src.clear()
-                .wrap_err(eyre!("Could not celar tracks from file: {:?}", path))?;
-        }
-        src.apply(dest.clone().try_into()?)
-            .wrap_err(eyre!("Could not apply new tags to track: {:?}", path))?;
-        if let Some(ref picture) = maybe_picture {
-            src.set_pictures(vec![picture.clone()])?;
-        }
-        src.write()
-            .wrap_err(eyre!("Could not write tags to track: {:?}", path))?;
-        dest.store().await?;
+        if let Err(e) = place(src, dest, mode, &pictures, &mut placed) {
+            error!("Import failed, rolling back {} files", placed.len());
+            rollback(placed, vec![], stashed, mode);
+            return Err(e);
+        }
         debug!("After tagging {:?}", src);
     }
+    let mut placed_extras = vec![];
+    for (src, dest) in extra_files.iter() {
+        if let Err(e) = place_extra(src, dest, mode, &mut placed_extras) {
+            error!("Import failed, rolling back {} files", placed.len());
+            rollback(placed, placed_extras, stashed, mode);
+            return Err(e);
+        }
+    }
+    extras.extend(placed_extras.iter().map(|(_, dest)| Extra {
+        path: dest.clone(),
+        release: final_release.mbid.clone(),
+    }));
+    let journal = Import {
+        id: None,
+        release: final_release.mbid.clone(),
+        mode,
+        source: Some(source),
+        timestamp: Utc::now().naive_utc(),
+        undone: None,
+        files: placed
+            .iter()
+            .zip(final_tracks.iter())
+            .map(|((original, _), (src, dest))| ImportedFile {
+                source: original.path.clone(),
+                destination: src.path.clone(),
+                track: dest.mbid.clone(),
+                hash: sources
+                    .iter()
+                    .position(|s| *s == original.path)
+                    .map(|i| hashes[i].clone()),
+                tags: src.get_all(),
+                original_tags: original.get_all(),
+                // Copies leave the source untouched
+                original_pictures: match mode {
+                    ImportMode::Copy | ImportMode::Reflink => vec![],
+                    _ => original.get_pictures().unwrap_or_default(),
+                },
+            })
+            .chain(placed_extras.iter().map(|(src, dest)| ImportedFile {
+                source: src.clone(),
+                destination: dest.clone(),
+                track: None,
+                hash: None,
+                tags: HashMap::new(),
+                original_tags: HashMap::new(),
+                original_pictures: vec![],
+            }))
+            .collect(),
+    };
+    // Only track the files once they are all in place
+    if let Err(e) = store_tracks(
+        &final_tracks,
+        &extras,
+        &journal,
+        &replaced,
+        &replaced_imports,
+        &kept,
+    )
+    .await
+    {
+        error!(
+            "Could not store the release, rolling back {} files",
+            placed.len()
+        );
+        rollback(placed, placed_extras, stashed, mode);
+        return Err(e);
+    }
+    delete_replaced(stashed);
 
     info!("Import done, took {:?}", start.elapsed());
     Ok(())
@@ -1,19 +1,72 @@
-use crate::models::{Artist, Format, Release, Track};
+use crate::models::{Artist, Extra, Format, Import, ImportedFile, Release, Track, TrackCopy};
+use crate::sanitize::Sanitizer;
+use crate::settings::{ImportMode, PathTemplate};
+use crate::template::{Condition, Template};
 use crate::track::format::Format as TrackFormat;
+use crate::track::picture::{Picture, PictureType};
 use crate::util::path_to_str;
 use crate::{DB, SETTINGS};
 use async_trait::async_trait;
 use eyre::{eyre, Result, WrapErr};
 use itertools::Itertools;
 use log::trace;
+use mime::Mime;
 use sqlx::sqlite::SqliteRow;
-use sqlx::{Encode, Pool, QueryBuilder, Row, Sqlite, Type};
+use sqlx::{Encode, Pool, QueryBuilder, Row, Sqlite, SqliteConnection, Type};
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::iter;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
+// The special purpose artist MusicBrainz credits compilations to
+static VARIOUS_ARTISTS_MBID: &str = "89ad4ac3-39f7-470e-963a-56509c546377";
+
+fn is_compilation(release: &Release) -> bool {
+    release.artists.iter().any(|a| {
+        a.mbid.as_deref() == Some(VARIOUS_ARTISTS_MBID)
+            || (a.mbid.is_none() && a.name == "Various Artists")
+    })
+}
+
+fn template_matches(
+    template: &PathTemplate,
+    release: Option<&Release>,
+    vars: &HashMap<String, Vec<String>>,
+) -> Result<bool> {
+    if let Some(release_type) = template.release_type.as_ref() {
+        let types: Vec<&String> = release
+            .map(|r| {
+                r.release_type
+                    .iter()
+                    .chain(r.secondary_types.iter())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !types.iter().any(|t| t.eq_ignore_ascii_case(release_type)) {
+            return Ok(false);
+        }
+    }
+    if let Some(compilation) = template.compilation {
+        if release.is_some_and(is_compilation) != compilation {
+            return Ok(false);
+        }
+    }
+    if let Some(media) = template.media.as_ref() {
+        let release_media = release.and_then(|r| r.media.as_ref());
+        if !release_media.is_some_and(|m| m.eq_ignore_ascii_case(media)) {
+            return Ok(false);
+        }
+    }
+    if let Some(query) = template.query.as_ref() {
+        if !Condition::from_str(query)?.holds(vars) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
 pub trait LibraryTrack {
     fn path(&self) -> Result<PathBuf>;
 }
@@ -24,19 +77,68 @@ impl LibraryTrack for Track {
             .get()
             .ok_or(eyre!("Could not read settings"))
             .wrap_err("While generating a path for the library")?;
-        let mut builder = self.fmt(settings.track_name.as_str())?;
-        builder.push('.');
-        builder.push_str(
-            self.format
-                .ok_or(eyre!("The given Track doesn't have an associated format"))?
-                .ext(),
-        );
+        let vars = self.vars()?;
+        let mut template = settings.track_name.as_str();
+        for candidate in settings.paths.templates.iter() {
+            if template_matches(candidate, self.release.as_ref(), &vars)? {
+                template = candidate.template.as_str();
+                break;
+            }
+        }
+        let sanitizer = Sanitizer::new(&settings.paths)?;
+        let vars = vars
+            .into_iter()
+            .map(|(k, v)| (k, v.iter().map(|s| sanitizer.value(s)).collect()))
+            .collect();
+        let rendered = Template::from_str(template)?.render(&vars);
+        let ext = self
+            .format
+            .ok_or(eyre!("The given Track doesn't have an associated format"))?
+            .ext();
         Ok(settings
             .library
-            .join(PathBuf::from_str(builder.as_str()).map_err(|e| eyre!(e))?))
+            .join(sanitizer.path(rendered.as_str(), ext)))
+    }
+}
+
+// Maps the keys users can filter tracks by onto the columns of the
+// tracks/releases tables
+fn track_column(key: &str) -> Option<&'static str> {
+    match key {
+        "mbid" | "track_mbid" => Some("tracks.mbid"),
+        "recording" | "recording_mbid" => Some("tracks.recording_mbid"),
+        "title" | "track_title" => Some("tracks.title"),
+        "disc" | "disc_number" => Some("tracks.disc"),
+        "number" | "track_number" => Some("tracks.number"),
+        "format" => Some("tracks.format"),
+        "path" => Some("tracks.path"),
+        "release" | "release_mbid" => Some("releases.mbid"),
+        "release_group" | "release_group_mbid" => Some("releases.release_group_mbid"),
+        "album" => Some("releases.title"),
+        "media" => Some("releases.media"),
+        "country" | "release_country" => Some("releases.country"),
+        "label" | "record_label" => Some("releases.label"),
+        "catalog_number" => Some("releases.catalog_no"),
+        "status" | "release_status" => Some("releases.status"),
+        "release_type" => Some("releases.release_type"),
+        _ => None,
     }
 }
 
+// Parses `key=value` filters from the command line into fields for Track::filter
+pub fn track_filters(filters: Vec<&String>) -> Result<Vec<(String, String)>> {
+    filters
+        .into_iter()
+        .map(|filter| {
+            let (key, value) = filter
+                .split_once('=')
+                .ok_or(eyre!("Invalid filter {}, expected key=value", filter))?;
+            let column = track_column(key).ok_or(eyre!("Unknown filter key: {}", key))?;
+            Ok((column.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
 pub trait Value<'args>: Encode<'args, Sqlite> + sqlx::Type<Sqlite> {}
 
 #[async_trait]
@@ -82,14 +184,16 @@ pub trait Fetch: Builder {
         Self: Sized;
 }
 
+// Writes go through a connection rather than the pool so that callers can
+// group them in a single transaction, keeping the library consistent
 #[async_trait]
 pub trait Store: Builder {
-    async fn store(&self) -> Result<()>;
+    async fn store(&self, conn: &mut SqliteConnection) -> Result<()>;
 }
 
 #[async_trait]
 pub trait Delete: Builder {
-    async fn delete(&self) -> Result<()>;
+    async fn delete(&self, conn: &mut SqliteConnection) -> Result<()>;
 }
 
 impl<T> Builder for T
@@ -105,6 +209,9 @@ where
         qb.push(Self::fields().join(","));
         qb.push(" FROM ");
         qb.push(Self::table());
+        if let Some(join) = Self::join() {
+            qb.push(join);
+        }
         if !fields.is_empty() {
             qb.push(" WHERE ");
             let len = fields.len();
@@ -116,9 +223,6 @@ where
                 }
             }
         }
-        if let Some(join) = Self::join() {
-            qb.push(join);
-        }
         for ex in extra.into_iter() {
             qb.push(ex);
         }
@@ -237,14 +341,14 @@ impl InTable for Artist {
 
 #[async_trait]
 impl Store for Artist {
-    async fn store(&self) -> Result<()> {
+    async fn store(&self, conn: &mut SqliteConnection) -> Result<()> {
         Self::store_builder()
             .build()
             .bind(&self.mbid)
             .bind(&self.name)
             .bind(&self.sort_name)
             .bind(serde_json::to_string(&self.instruments)?)
-            .execute(DB.get().ok_or(eyre!("Could not get database"))?)
+            .execute(&mut *conn)
             .await?;
         Ok(())
     }
@@ -252,65 +356,80 @@ impl Store for Artist {
 
 #[async_trait]
 impl Delete for Artist {
-    async fn delete(&self) -> Result<()> {
-        let db = DB.get().ok_or(eyre!("Could not get database"))?;
-        unlink(db, "track_artists", self.mbid.as_ref()).await?;
-        unlink(db, "track_performers", self.mbid.as_ref()).await?;
-        unlink(db, "track_engigneers", self.mbid.as_ref()).await?;
-        unlink(db, "track_mixers", self.mbid.as_ref()).await?;
-        unlink(db, "track_producers", self.mbid.as_ref()).await?;
-        unlink(db, "track_lyricists", self.mbid.as_ref()).await?;
-        unlink(db, "track_writers", self.mbid.as_ref()).await?;
-        unlink(db, "track_composers", self.mbid.as_ref()).await?;
-        unlink(db, "release_artists", self.mbid.as_ref()).await?;
+    async fn delete(&self, conn: &mut SqliteConnection) -> Result<()> {
+        unlink(conn, "track_artists", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_performers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_engigneers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_mixers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_producers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_lyricists", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_writers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_composers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "release_artists", self.mbid.as_ref()).await?;
         Self::delete_builder(vec![("mbid", &self.mbid)])
             .build()
-            .execute(db)
+            .execute(&mut *conn)
             .await?;
         Ok(())
     }
 }
 
+// The artists credited through the given link table, in the order of the credit
 async fn resolve(db: &Pool<Sqlite>, table: &str, mbid: Option<&String>) -> Result<Vec<Artist>> {
-    Artist::query_builder::<String, String>(vec![], vec![])
-        .push(format!(
-            " WHERE mbid = (SELECT artist FROM {} WHERE ref =",
-            table
-        ))
-        .push_bind(mbid)
-        .push(")")
+    let mut qb = QueryBuilder::new(format!(
+        "SELECT {}, {}.join_phrase AS join_phrase FROM artists INNER JOIN {} ON {}.artist = artists.mbid WHERE {}.ref = ",
+        Artist::fields()
+            .iter()
+            .map(|f| format!("artists.{} AS {}", f, f))
+            .join(","),
+        table,
+        table,
+        table,
+        table
+    ));
+    qb.push_bind(mbid)
+        .push(format!(" ORDER BY {}.position", table))
         .build()
-        .try_map(Artist::decode)
+        .try_map(|row: SqliteRow| {
+            let join_phrase = row.try_get("join_phrase")?;
+            Ok(Artist {
+                join_phrase,
+                ..Artist::decode(row)?
+            })
+        })
         .fetch_all(db)
         .await
         .map_err(|e| eyre!(e))
 }
 
 async fn link(
-    db: &Pool<Sqlite>,
+    conn: &mut SqliteConnection,
     table: &str,
     mbid: Option<&String>,
     artist: &Artist,
+    position: usize,
 ) -> Result<()> {
     sqlx::query(
         format!(
-            "INSERT OR REPLACE INTO {} (ref, artist) VALUES (?, ?)",
+            "INSERT OR REPLACE INTO {} (ref, artist, position, join_phrase) VALUES (?, ?, ?, ?)",
             table
         )
         .as_str(),
     )
     .bind(mbid)
     .bind(artist.mbid.as_ref())
-    .execute(db)
+    .bind(position as i64)
+    .bind(artist.join_phrase.as_ref())
+    .execute(conn)
     .await?;
     Ok(())
 }
 
-async fn unlink(db: &Pool<Sqlite>, table: &str, mbid: Option<&String>) -> Result<()> {
+async fn unlink(conn: &mut SqliteConnection, table: &str, mbid: Option<&String>) -> Result<()> {
     sqlx::query(format!("DELETE FROM {} WHERE ref = ? OR artist = ?", table).as_str())
         .bind(mbid)
         .bind(mbid)
-        .execute(db)
+        .execute(conn)
         .await?;
     Ok(())
 }
@@ -323,6 +442,7 @@ impl InTable for Track {
     fn fields() -> Vec<&'static str> {
         vec![
             "tracks.mbid AS t_mbid",
+            "tracks.recording_mbid AS t_recording_mbid",
             "tracks.title AS t_title",
             "tracks.length AS t_length",
             "tracks.disc AS t_disc",
@@ -344,6 +464,7 @@ impl InTable for Track {
             "releases.catalog_no AS r_catalog_no",
             "releases.status AS r_status",
             "releases.release_type AS r_release_type",
+            "releases.secondary_types AS r_secondary_types",
             "releases.date AS r_date",
             "releases.original_date AS r_original_date",
             "releases.script AS r_script",
@@ -361,6 +482,7 @@ impl InTable for Track {
             "release",
             "format",
             "path",
+            "recording_mbid",
         ]
     }
     fn join() -> Option<&'static str> {
@@ -372,10 +494,11 @@ impl InTable for Track {
     {
         Ok(Self {
             mbid: row.try_get("t_mbid").ok(),
+            recording_mbid: row.try_get("t_recording_mbid").ok(),
             title: row.try_get("t_title")?,
             artists: vec![],
             length: row
-                .try_get("t_title")
+                .try_get("t_length")
                 .ok()
                 .map(|d: i64| Duration::from_secs(d as u64)),
             disc: row.try_get("t_disc").ok().map(|d: i64| d as u64),
@@ -421,11 +544,10 @@ impl InTable for Track {
 
 #[async_trait]
 impl Store for Track {
-    async fn store(&self) -> Result<()> {
+    async fn store(&self, conn: &mut SqliteConnection) -> Result<()> {
         if let Some(rel) = &self.release {
-            rel.store().await?;
+            rel.store(&mut *conn).await?;
         }
-        let db = DB.get().ok_or(eyre!("Could not get database"))?;
         Track::store_builder()
             .build()
             .bind(&self.mbid)
@@ -441,40 +563,64 @@ impl Store for Track {
                 Err(eyre!("The given track doesn't have an associated path")),
                 path_to_str,
             )?)
-            .execute(db)
+            .bind(&self.recording_mbid)
+            .execute(&mut *conn)
             .await?;
 
-        for artist in self.artists.iter() {
-            artist.store().await?;
-            link(db, "track_artists", self.mbid.as_ref(), artist).await?;
+        // drop the links to artists which may no longer be credited
+        unlink(&mut *conn, "track_artists", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_performers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_engigneers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_mixers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_producers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_lyricists", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_writers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_composers", self.mbid.as_ref()).await?;
+        for (i, artist) in self.artists.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(&mut *conn, "track_artists", self.mbid.as_ref(), artist, i).await?;
         }
-        for artist in self.performers.iter() {
-            artist.store().await?;
-            link(db, "track_performers", self.mbid.as_ref(), artist).await?;
+        for (i, artist) in self.performers.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(
+                &mut *conn,
+                "track_performers",
+                self.mbid.as_ref(),
+                artist,
+                i,
+            )
+            .await?;
         }
-        for artist in self.engigneers.iter() {
-            artist.store().await?;
-            link(db, "track_engigneers", self.mbid.as_ref(), artist).await?;
+        for (i, artist) in self.engigneers.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(
+                &mut *conn,
+                "track_engigneers",
+                self.mbid.as_ref(),
+                artist,
+                i,
+            )
+            .await?;
         }
-        for artist in self.mixers.iter() {
-            artist.store().await?;
-            link(db, "track_mixers", self.mbid.as_ref(), artist).await?;
+        for (i, artist) in self.mixers.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(&mut *conn, "track_mixers", self.mbid.as_ref(), artist, i).await?;
         }
-        for artist in self.producers.iter() {
-            artist.store().await?;
-            link(db, "track_producers", self.mbid.as_ref(), artist).await?;
+        for (i, artist) in self.producers.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(&mut *conn, "track_producers", self.mbid.as_ref(), artist, i).await?;
         }
-        for artist in self.lyricists.iter() {
-            artist.store().await?;
-            link(db, "track_lyricists", self.mbid.as_ref(), artist).await?;
+        for (i, artist) in self.lyricists.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(&mut *conn, "track_lyricists", self.mbid.as_ref(), artist, i).await?;
         }
-        for artist in self.writers.iter() {
-            artist.store().await?;
-            link(db, "track_writers", self.mbid.as_ref(), artist).await?;
+        for (i, artist) in self.writers.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(&mut *conn, "track_writers", self.mbid.as_ref(), artist, i).await?;
         }
-        for artist in self.composers.iter() {
-            artist.store().await?;
-            link(db, "track_composers", self.mbid.as_ref(), artist).await?;
+        for (i, artist) in self.composers.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(&mut *conn, "track_composers", self.mbid.as_ref(), artist, i).await?;
         }
         Ok(())
     }
@@ -482,19 +628,23 @@ impl Store for Track {
 
 #[async_trait]
 impl Delete for Track {
-    async fn delete(&self) -> Result<()> {
-        let db = DB.get().ok_or(eyre!("Could not get database"))?;
-        unlink(db, "track_artists", self.mbid.as_ref()).await?;
-        unlink(db, "track_performers", self.mbid.as_ref()).await?;
-        unlink(db, "track_engigneers", self.mbid.as_ref()).await?;
-        unlink(db, "track_mixers", self.mbid.as_ref()).await?;
-        unlink(db, "track_producers", self.mbid.as_ref()).await?;
-        unlink(db, "track_lyricists", self.mbid.as_ref()).await?;
-        unlink(db, "track_writers", self.mbid.as_ref()).await?;
-        unlink(db, "track_composers", self.mbid.as_ref()).await?;
+    async fn delete(&self, conn: &mut SqliteConnection) -> Result<()> {
+        unlink(conn, "track_artists", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_performers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_engigneers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_mixers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_producers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_lyricists", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_writers", self.mbid.as_ref()).await?;
+        unlink(&mut *conn, "track_composers", self.mbid.as_ref()).await?;
+        // Copies can't be tracked without the track they are a copy of
+        sqlx::query("DELETE FROM copies WHERE track = ?")
+            .bind(&self.mbid)
+            .execute(&mut *conn)
+            .await?;
         Self::delete_builder(vec![("mbid", &self.mbid)])
             .build()
-            .execute(db)
+            .execute(&mut *conn)
             .await?;
         Ok(())
     }
@@ -519,6 +669,7 @@ impl InTable for Release {
             "catalog_no AS r_catalog_no",
             "status AS r_status",
             "release_type AS r_release_type",
+            "secondary_types AS r_secondary_types",
             "date AS r_date",
             "original_date AS r_original_date",
             "script AS r_script",
@@ -538,6 +689,7 @@ impl InTable for Release {
             "catalog_no",
             "status",
             "release_type",
+            "secondary_types",
             "date",
             "original_date",
             "script",
@@ -564,6 +716,8 @@ impl InTable for Release {
             catalog_no: row.try_get("r_catalog_no").ok(),
             status: row.try_get("r_status").ok(),
             release_type: row.try_get("r_release_type").ok(),
+            secondary_types: serde_json::from_str(row.try_get("r_secondary_types")?)
+                .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
             date: row.try_get("r_date").ok(),
             original_date: row.try_get("r_original_date").ok(),
             script: row.try_get("r_script").ok(),
@@ -577,8 +731,7 @@ impl InTable for Release {
 
 #[async_trait]
 impl Store for Release {
-    async fn store(&self) -> Result<()> {
-        let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    async fn store(&self, conn: &mut SqliteConnection) -> Result<()> {
         Release::store_builder()
             .build()
             .bind(&self.mbid)
@@ -593,14 +746,16 @@ impl Store for Release {
             .bind(&self.catalog_no)
             .bind(&self.status)
             .bind(&self.release_type)
+            .bind(serde_json::to_string(&self.secondary_types)?)
             .bind(self.date)
             .bind(self.original_date)
             .bind(&self.script)
-            .execute(db)
+            .execute(&mut *conn)
             .await?;
-        for artist in self.artists.iter() {
-            artist.store().await?;
-            link(db, "release_artists", self.mbid.as_ref(), artist).await?;
+        unlink(&mut *conn, "release_artists", self.mbid.as_ref()).await?;
+        for (i, artist) in self.artists.iter().enumerate() {
+            artist.store(&mut *conn).await?;
+            link(&mut *conn, "release_artists", self.mbid.as_ref(), artist, i).await?;
         }
         Ok(())
     }
@@ -608,13 +763,326 @@ impl Store for Release {
 
 #[async_trait]
 impl Delete for Release {
-    async fn delete(&self) -> Result<()> {
-        let db = DB.get().ok_or(eyre!("Could not get database"))?;
-        unlink(db, "release_artists", self.mbid.as_ref()).await?;
+    async fn delete(&self, conn: &mut SqliteConnection) -> Result<()> {
+        unlink(conn, "release_artists", self.mbid.as_ref()).await?;
         Self::delete_builder(vec![("mbid", &self.mbid)])
             .build()
-            .execute(db)
+            .execute(&mut *conn)
             .await?;
         Ok(())
     }
 }
+
+#[async_trait]
+impl InTable for Extra {
+    fn table() -> &'static str {
+        "extras"
+    }
+    fn fields() -> Vec<&'static str> {
+        vec!["path", "release"]
+    }
+    fn store_fields() -> Vec<&'static str> {
+        Extra::fields()
+    }
+    fn join() -> Option<&'static str> {
+        None
+    }
+    fn decode(row: SqliteRow) -> Result<Self, sqlx::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            path: PathBuf::from(row.try_get::<&str, _>("path")?),
+            release: row.try_get("release").ok(),
+        })
+    }
+    async fn fill_relationships(&mut self, _: &Pool<Sqlite>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for Extra {
+    async fn store(&self, conn: &mut SqliteConnection) -> Result<()> {
+        Extra::store_builder()
+            .build()
+            .bind(path_to_str(&self.path)?)
+            .bind(&self.release)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Delete for Extra {
+    async fn delete(&self, conn: &mut SqliteConnection) -> Result<()> {
+        Self::delete_builder(vec![("path", path_to_str(&self.path)?)])
+            .build()
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InTable for TrackCopy {
+    fn table() -> &'static str {
+        "copies"
+    }
+    fn fields() -> Vec<&'static str> {
+        vec!["path", "track", "format"]
+    }
+    fn store_fields() -> Vec<&'static str> {
+        TrackCopy::fields()
+    }
+    fn join() -> Option<&'static str> {
+        None
+    }
+    fn decode(row: SqliteRow) -> Result<Self, sqlx::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            path: PathBuf::from(row.try_get::<&str, _>("path")?),
+            track: row.try_get("track").ok(),
+            format: row
+                .try_get("format")
+                .map_or(Ok(None), |f| TrackFormat::from_ext(f).map(Some))
+                .map_err(|e| sqlx::Error::Decode(e.into()))?,
+        })
+    }
+    async fn fill_relationships(&mut self, _: &Pool<Sqlite>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for TrackCopy {
+    async fn store(&self, conn: &mut SqliteConnection) -> Result<()> {
+        TrackCopy::store_builder()
+            .build()
+            .bind(path_to_str(&self.path)?)
+            .bind(&self.track)
+            .bind(self.format.map(String::from))
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Delete for TrackCopy {
+    async fn delete(&self, conn: &mut SqliteConnection) -> Result<()> {
+        Self::delete_builder(vec![("path", path_to_str(&self.path)?)])
+            .build()
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InTable for Import {
+    fn table() -> &'static str {
+        "imports"
+    }
+    fn fields() -> Vec<&'static str> {
+        vec!["id", "release", "mode", "source", "timestamp", "undone"]
+    }
+    fn store_fields() -> Vec<&'static str> {
+        Import::fields()
+    }
+    fn join() -> Option<&'static str> {
+        None
+    }
+    fn decode(row: SqliteRow) -> Result<Self, sqlx::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            id: row.try_get("id").ok(),
+            release: row.try_get("release").ok(),
+            mode: ImportMode::from_str(row.try_get("mode")?)
+                .map_err(|e| sqlx::Error::Decode(e.into()))?,
+            source: row
+                .try_get("source")
+                .map_or(None, |p: &str| PathBuf::from_str(p).ok()),
+            timestamp: row.try_get("timestamp")?,
+            undone: row.try_get("undone").ok(),
+            files: vec![],
+        })
+    }
+    async fn fill_relationships(&mut self, db: &Pool<Sqlite>) -> Result<()> {
+        self.files = sqlx::query(
+            "SELECT source, destination, track, hash, tags, original_tags FROM import_files WHERE import = ?",
+        )
+        .bind(self.id)
+        .try_map(|row: SqliteRow| {
+            Ok(ImportedFile {
+                source: PathBuf::from(row.try_get::<&str, _>("source")?),
+                destination: PathBuf::from(row.try_get::<&str, _>("destination")?),
+                track: row.try_get("track").ok(),
+                hash: row.try_get("hash").ok(),
+                tags: serde_json::from_str(row.try_get("tags")?)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                original_tags: serde_json::from_str(row.try_get("original_tags")?)
+                    .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                original_pictures: vec![],
+            })
+        })
+        .fetch_all(db)
+        .await?;
+        let pictures = sqlx::query(
+            "SELECT source, mime, type, description, data FROM import_pictures WHERE import = ? ORDER BY rowid",
+        )
+        .bind(self.id)
+        .try_map(|row: SqliteRow| {
+            Ok((
+                PathBuf::from(row.try_get::<&str, _>("source")?),
+                Picture {
+                    mime_type: Mime::from_str(row.try_get("mime")?)
+                        .map_err(|e| sqlx::Error::Decode(Box::new(e)))?,
+                    picture_type: PictureType::from(row.try_get::<u8, _>("type")?),
+                    description: row.try_get("description")?,
+                    data: row.try_get("data")?,
+                },
+            ))
+        })
+        .fetch_all(db)
+        .await?;
+        for (source, picture) in pictures.into_iter() {
+            if let Some(file) = self.files.iter_mut().find(|f| f.source == source) {
+                file.original_pictures.push(picture);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for Import {
+    async fn store(&self, conn: &mut SqliteConnection) -> Result<()> {
+        let id = Import::store_builder()
+            .build()
+            .bind(self.id)
+            .bind(&self.release)
+            .bind(self.mode.to_string())
+            .bind(self.source.as_ref().map(path_to_str).transpose()?)
+            .bind(self.timestamp)
+            .bind(self.undone)
+            .execute(&mut *conn)
+            .await?
+            .last_insert_rowid();
+        sqlx::query("DELETE FROM import_files WHERE import = ?")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM import_pictures WHERE import = ?")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        for file in self.files.iter() {
+            sqlx::query(
+                "INSERT INTO import_files (import, source, destination, track, hash, tags, original_tags) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(path_to_str(&file.source)?)
+            .bind(path_to_str(&file.destination)?)
+            .bind(&file.track)
+            .bind(&file.hash)
+            .bind(serde_json::to_string(&file.tags)?)
+            .bind(serde_json::to_string(&file.original_tags)?)
+            .execute(&mut *conn)
+            .await?;
+            for picture in file.original_pictures.iter() {
+                sqlx::query(
+                    "INSERT INTO import_pictures (import, source, mime, type, description, data) VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(id)
+                .bind(path_to_str(&file.source)?)
+                .bind(picture.mime_type.to_string())
+                .bind(u8::from(picture.picture_type))
+                .bind(&picture.description)
+                .bind(&picture.data)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Removes the releases left without any track and the artists which are no
+// longer linked to any release or track
+pub async fn prune(conn: &mut SqliteConnection) -> Result<()> {
+    let orphan_releases =
+        "SELECT mbid FROM releases WHERE mbid NOT IN (SELECT release FROM tracks WHERE release IS NOT NULL)";
+    sqlx::query(
+        format!(
+            "DELETE FROM release_artists WHERE ref IN ({})",
+            orphan_releases
+        )
+        .as_str(),
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(format!("DELETE FROM extras WHERE release IN ({})", orphan_releases).as_str())
+        .execute(&mut *conn)
+        .await?;
+    let releases =
+        sqlx::query(format!("DELETE FROM releases WHERE mbid IN ({})", orphan_releases).as_str())
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+    // A single NULL in the NOT IN list would keep every artist
+    let linked = [
+        "release_artists",
+        "track_artists",
+        "track_performers",
+        "track_engigneers",
+        "track_mixers",
+        "track_producers",
+        "track_lyricists",
+        "track_writers",
+        "track_composers",
+    ]
+    .map(|table| format!("SELECT artist FROM {} WHERE artist IS NOT NULL", table))
+    .join(" UNION ");
+    let artists =
+        sqlx::query(format!("DELETE FROM artists WHERE mbid NOT IN ({})", linked).as_str())
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+    trace!("Pruned {} releases and {} artists", releases, artists);
+    Ok(())
+}
+
+// The copies kept in the library next to the given tracks
+pub async fn copies_of(tracks: &[Track]) -> Result<Vec<TrackCopy>> {
+    let mut copies = vec![];
+    for mbid in tracks.iter().filter_map(|t| t.mbid.as_ref()) {
+        copies.extend(TrackCopy::filter(vec![("track", mbid)], vec![]).await?);
+    }
+    Ok(copies)
+}
+
+// The mode each track was last brought into the library with, by track mbid,
+// as recorded in the import journal. Files kept as copies don't count
+pub async fn import_modes() -> Result<HashMap<String, ImportMode>> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let rows = sqlx::query(
+        "SELECT import_files.track, imports.mode FROM import_files JOIN imports ON imports.id = import_files.import WHERE imports.undone IS NULL AND import_files.track IS NOT NULL AND import_files.destination NOT IN (SELECT path FROM copies) ORDER BY imports.id",
+    )
+    .try_map(|row: SqliteRow| {
+        Ok((
+            row.try_get::<String, _>("track")?,
+            ImportMode::from_str(row.try_get("mode")?)
+                .map_err(|e| sqlx::Error::Decode(e.into()))?,
+        ))
+    })
+    .fetch_all(db)
+    .await?;
+    // Later imports take the place of earlier ones
+    Ok(rows.into_iter().collect())
+}
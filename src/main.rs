@@ -1,21 +1,30 @@
+mod collision;
 mod fetch;
 mod library;
 mod models;
 mod rank;
+mod sanitize;
 mod settings;
+mod template;
 mod theme;
 mod track;
 mod util;
 
+mod art;
+mod duplicates;
+mod history;
 mod import;
 mod list;
+mod remove;
+mod sync;
 mod update;
+mod write;
 
 // automatically generated sql migrations
 mod generated;
 
 use async_once_cell::OnceCell;
-use clap::{arg, Command};
+use clap::{arg, ArgAction, Command};
 use eyre::{eyre, Result};
 use lazy_static::lazy_static;
 use log::{error, info};
@@ -25,7 +34,7 @@ use sqlx_migrate::Migrator;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use settings::Settings;
+use settings::{ImportMode, Settings};
 
 pub const CLI_NAME: &str = "tagger";
 pub const VERSION: &str = "0.1.0";
@@ -64,12 +73,85 @@ fn cli() -> Command<'static> {
                 .alias("fix")
                 .about("Applies the needed changes to all the out-of-date tags of all files being tracked")
                 .arg_required_else_help(false)
+                .arg(arg!(DRY_RUN: -n --"dry-run" "Only print the changes, without touching any file or the library").action(ArgAction::SetTrue))
                 .arg(arg!(FILTER: [FILTER] ... "Filter the collection items to fix")),
         )
+        .subcommand(
+            Command::new("write")
+                .about("Writes the metadata stored in the library back into the tags of the files being tracked")
+                .arg_required_else_help(false)
+                .arg(arg!(DRY_RUN: -n --"dry-run" "Only print the changes, without touching any file").action(ArgAction::SetTrue))
+                .arg(arg!(FILTER: [FILTER] ... "Filter the collection items to write")),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Refreshes the metadata in the library with the latest data from MusicBrainz")
+                .arg_required_else_help(false)
+                .arg(arg!(WRITE: -w --write "Also write the new metadata to the tags of the files").action(ArgAction::SetTrue))
+                .arg(arg!(MOVE: -m --move "Also move the files whose path changed").action(ArgAction::SetTrue))
+                .arg(arg!(DRY_RUN: -n --"dry-run" "Only print the changes, without touching any file or the library").action(ArgAction::SetTrue))
+                .arg(arg!(FILTER: [FILTER] ... "Filter the collection items to sync")),
+        )
+        .subcommand(
+            Command::new("remove")
+                .alias("rm")
+                .about("Removes the matching tracks from the library, along with releases and artists left without any track")
+                .arg_required_else_help(true)
+                .arg(arg!(DELETE_FILES: -d --"delete-files" "Also delete the audio files, their cover and the folders left empty").action(ArgAction::SetTrue))
+                .arg(arg!(FILTER: <FILTER> ... "Filter the collection items to remove")),
+        )
+        .subcommand(
+            Command::new("duplicates")
+                .alias("dups")
+                .about("Finds the music present more than once in the library, offering to keep only the best copy")
+                .arg(arg!(BY: -b --by <BY> ... "What makes two copies duplicates, defaults to release and release-group. Tracks shared by an album and a compilation are the same recording").required(false).value_parser(["release", "release-group", "recording", "fingerprint"]))
+                .arg(arg!(DRY_RUN: -n --"dry-run" "Only list the duplicates, without touching any file or the library").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("art")
+                .about("Manages the album art of the music being tracked")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("fetch")
+                        .about("Fetches the art of the albums without a cover")
+                        .arg(arg!(DRY_RUN: -n --"dry-run" "Only list the albums without a cover").action(ArgAction::SetTrue))
+                        .arg(arg!(FILTER: [FILTER] ... "Filter the albums to fetch art for")),
+                )
+                .subcommand(
+                    Command::new("replace")
+                        .about("Searches the providers again for a new cover, asking which one to use")
+                        .arg_required_else_help(true)
+                        .arg(arg!(FILTER: <FILTER> ... "Filter the albums whose cover is replaced")),
+                )
+                .subcommand(
+                    Command::new("embed")
+                        .about("Embeds the images saved next to the tracks into their files")
+                        .arg(arg!(FILTER: [FILTER] ... "Filter the albums to embed art into")),
+                )
+                .subcommand(
+                    Command::new("extract")
+                        .about("Saves the pictures embedded in the tracks next to them")
+                        .arg(arg!(FILTER: [FILTER] ... "Filter the albums to extract art from")),
+                ),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Lists all past imports")
+        )
+        .subcommand(
+            Command::new("undo")
+                .about("Undoes a past import, removing its files and tracks from the library")
+                .arg_required_else_help(true)
+                .arg(arg!(ID: <ID> "The id of the import, as shown by history").value_parser(clap::value_parser!(i64))),
+        )
         .subcommand(
             Command::new("import")
                 .about("Imports an album directory (recursively) into the library")
                 .arg_required_else_help(true)
+                .arg(arg!(MODE: -m --mode <MODE> "How files are brought into the library, overriding the import.mode setting").required(false).value_parser(["copy", "move", "hardlink", "symlink", "reflink", "in-place"]))
+                .arg(arg!(INCREMENTAL: -i --incremental "Skip the folders which have already been imported").action(ArgAction::SetTrue))
+                .arg(arg!(DRY_RUN: -n --"dry-run" "Only print the changes, without touching any file or the library").action(ArgAction::SetTrue))
                 .arg(arg!(PATH: <PATH> ... "Folder(s) to import as an album").value_parser(clap::value_parser!(PathBuf))),
         )
 }
@@ -145,7 +227,69 @@ async fn main() -> Result<()> {
                         .get_many::<String>("FILTER")
                         .map(|i| i.into_iter().collect::<Vec<_>>())
                         .unwrap_or_default();
-                    update::update(filters).await
+                    let dry_run = *sub_matches.get_one::<bool>("DRY_RUN").unwrap_or(&false);
+                    update::update(filters, dry_run).await
+                }
+                ("write", sub_matches) => {
+                    let filters = sub_matches
+                        .get_many::<String>("FILTER")
+                        .map(|i| i.into_iter().collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    let dry_run = *sub_matches.get_one::<bool>("DRY_RUN").unwrap_or(&false);
+                    write::write(filters, dry_run).await
+                }
+                ("sync", sub_matches) => {
+                    let filters = sub_matches
+                        .get_many::<String>("FILTER")
+                        .map(|i| i.into_iter().collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    let write = *sub_matches.get_one::<bool>("WRITE").unwrap_or(&false);
+                    let relocate = *sub_matches.get_one::<bool>("MOVE").unwrap_or(&false);
+                    let dry_run = *sub_matches.get_one::<bool>("DRY_RUN").unwrap_or(&false);
+                    sync::sync(filters, write, relocate, dry_run).await
+                }
+                ("remove", sub_matches) => {
+                    let filters = sub_matches
+                        .get_many::<String>("FILTER")
+                        .map(|i| i.into_iter().collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    let delete_files = *sub_matches
+                        .get_one::<bool>("DELETE_FILES")
+                        .unwrap_or(&false);
+                    remove::remove(filters, delete_files).await
+                }
+                ("duplicates", sub_matches) => {
+                    let by = match sub_matches.get_many::<String>("BY") {
+                        Some(by) => by
+                            .map(|b| b.parse::<duplicates::By>())
+                            .collect::<Result<Vec<_>>>()?,
+                        None => vec![duplicates::By::Release, duplicates::By::ReleaseGroup],
+                    };
+                    let dry_run = *sub_matches.get_one::<bool>("DRY_RUN").unwrap_or(&false);
+                    duplicates::duplicates(by, dry_run).await
+                }
+                ("art", sub_matches) => {
+                    let (action, sub_matches) = sub_matches
+                        .subcommand()
+                        .ok_or(eyre!("Expected an art subcommand"))?;
+                    let filters = sub_matches
+                        .get_many::<String>("FILTER")
+                        .map(|i| i.into_iter().collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    let dry_run = sub_matches
+                        .try_get_one::<bool>("DRY_RUN")
+                        .ok()
+                        .flatten()
+                        .copied()
+                        .unwrap_or(false);
+                    art::art(action.parse::<art::Action>()?, filters, dry_run).await
+                }
+                ("history", _) => history::history().await,
+                ("undo", sub_matches) => {
+                    let id = *sub_matches
+                        .get_one::<i64>("ID")
+                        .ok_or(eyre!("Expected the id of an import to undo"))?;
+                    history::undo(id).await
                 }
                 ("import", sub_matches) => {
                     let stream = sub_matches
@@ -153,8 +297,20 @@ async fn main() -> Result<()> {
                         .ok_or(eyre!("Expected at least one path argument to import"))?
                         .into_iter()
                         .collect::<Vec<_>>();
+                    let mode = match sub_matches.get_one::<String>("MODE") {
+                        Some(m) => m.parse::<ImportMode>()?,
+                        None => {
+                            SETTINGS
+                                .get()
+                                .ok_or(eyre!("Could not read settings"))?
+                                .import
+                                .mode
+                        }
+                    };
+                    let incremental = *sub_matches.get_one::<bool>("INCREMENTAL").unwrap_or(&false);
+                    let dry_run = *sub_matches.get_one::<bool>("DRY_RUN").unwrap_or(&false);
                     for p in stream.iter() {
-                        import::import(p).await?;
+                        import::import(p, mode, incremental, dry_run).await?;
                     }
                     Ok(())
                 }
@@ -1,14 +1,17 @@
-use chrono::NaiveDate;
-use eyre::{eyre, Context, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use eyre::Result;
 use sqlx::FromRow;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
-use strfmt::strfmt;
 
+use crate::settings::ImportMode;
+use crate::template::Template;
 use crate::track::format::Format as TrackFormat;
 use crate::track::key::TagKey;
+use crate::track::picture::Picture;
 use crate::util::path_to_str;
 
 pub const UNKNOWN_ARTIST: &str = "(unkown artist)";
@@ -26,6 +29,7 @@ pub struct Artist {
 #[derive(Clone, Debug, FromRow)]
 pub struct Track {
     pub mbid: Option<String>,
+    pub recording_mbid: Option<String>,
     pub title: String,
     pub artists: Vec<Artist>,
     pub length: Option<Duration>,
@@ -66,11 +70,59 @@ pub struct Release {
     pub catalog_no: Option<String>,
     pub status: Option<String>,
     pub release_type: Option<String>,
+    // Such as soundtrack, live or compilation
+    pub secondary_types: Vec<String>,
     pub date: Option<NaiveDate>,
     pub original_date: Option<NaiveDate>,
     pub script: Option<String>,
 }
 
+// A non-audio file belonging to a release, like its cover, which follows the
+// album folder around the library
+#[derive(Clone, Debug)]
+pub struct Extra {
+    pub path: PathBuf,
+    pub release: Option<String>,
+}
+
+// Another copy of a track, kept in the library next to the one the track
+// points to when an album is imported again while keeping both
+#[derive(Clone, Debug)]
+pub struct TrackCopy {
+    pub path: PathBuf,
+    pub track: Option<String>,
+    pub format: Option<TrackFormat>,
+}
+
+// An entry of the import journal, allowing imports to be undone
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub id: Option<i64>,
+    pub release: Option<String>,
+    pub mode: ImportMode,
+    // The directory the import was run on
+    pub source: Option<PathBuf>,
+    pub timestamp: NaiveDateTime,
+    pub undone: Option<NaiveDateTime>,
+    pub files: Vec<ImportedFile>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportedFile {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub track: Option<String>,
+    // Content hash of the source before the import
+    pub hash: Option<String>,
+    // Raw tags as written by the import
+    pub tags: HashMap<String, Vec<String>>,
+    // Raw tags of the source before the import, embedded pictures excluded
+    pub original_tags: HashMap<String, Vec<String>>,
+    // Pictures embedded in the source before the import, only kept when the
+    // import changes the source
+    pub original_pictures: Vec<Picture>,
+}
+
 pub trait GroupTracks {
     fn group_tracks(self) -> Result<(Release, Vec<Track>)>;
 }
@@ -151,91 +203,96 @@ impl Artists for Vec<Artist> {
 }
 
 pub trait Format {
-    fn fmt(&self, template: &str) -> Result<String>;
+    // The values templates can refer to, multi-valued fields have one entry
+    // per value
+    fn vars(&self) -> Result<HashMap<String, Vec<String>>>;
+
+    fn fmt(&self, template: &str) -> Result<String> {
+        let template = Template::from_str(template)?;
+        Ok(template.render(&self.vars()?))
+    }
 }
 
 impl Format for Artist {
-    fn fmt(&self, template: &str) -> Result<String> {
+    fn vars(&self) -> Result<HashMap<String, Vec<String>>> {
         let mut vars = HashMap::new();
-        vars.insert("mbid".to_string(), self.mbid.clone().unwrap_or_default());
-        vars.insert("name".to_string(), self.name.clone());
+        vars.insert("mbid".to_string(), self.mbid.clone().into_iter().collect());
+        vars.insert("name".to_string(), vec![self.name.clone()]);
         vars.insert(
             "join_phrase".to_string(),
-            self.join_phrase.clone().unwrap_or_default(),
+            self.join_phrase.clone().into_iter().collect(),
         );
         vars.insert(
             "sort_name".to_string(),
-            self.sort_name.clone().unwrap_or_default(),
+            self.sort_name.clone().into_iter().collect(),
         );
-        vars.insert(
-            "instruments".to_string(),
-            self.instruments.join(", "), // TODO
-        );
-        strfmt(template, &vars)
-            .map_err(|e| eyre!(e))
-            .wrap_err(eyre!("Error while formatting artist string"))
+        vars.insert("instruments".to_string(), self.instruments.clone());
+        Ok(vars)
     }
 }
 
 impl Format for Track {
-    fn fmt(&self, template: &str) -> Result<String> {
-        let multiple_vars: HashMap<String, Vec<String>> = self.clone().try_into()?;
-        let mut vars: HashMap<String, String> = multiple_vars
-            .into_iter()
-            .map(|(k, v)| (k, v.join(", "))) // TODO
-            .collect();
+    fn vars(&self) -> Result<HashMap<String, Vec<String>>> {
+        let mut vars: HashMap<String, Vec<String>> = self.clone().try_into()?;
         // Multiple value fields are different. The version held in the Track::artists
         // data structure also holds the information for mergining the various
         // artists into a single string. We therefore generate it from the
         // original track instance
-        vars.insert(TagKey::Artist.to_string(), self.artists.joined());
-        vars.insert(TagKey::Artists.to_string(), self.artists.joined());
-        vars.insert(TagKey::OriginalArtist.to_string(), self.artists.joined());
+        vars.insert(TagKey::Artist.to_string(), vec![self.artists.joined()]);
         vars.insert(
-            TagKey::ArtistSortOrder.to_string(),
-            self.artists.sort_order_joined(),
+            TagKey::OriginalArtist.to_string(),
+            vec![self.artists.joined()],
         );
         vars.insert(
-            TagKey::Genre.to_string(),
-            self.genres.join(", "), // TODO
+            TagKey::ArtistSortOrder.to_string(),
+            vec![self.artists.sort_order_joined()],
         );
+        vars.insert(TagKey::Genre.to_string(), self.genres.clone());
         if let Some(release) = self.release.as_ref() {
-            vars.insert(TagKey::AlbumArtist.to_string(), release.artists.joined());
+            release.primary_type(&mut vars);
+            vars.insert(
+                TagKey::AlbumArtist.to_string(),
+                vec![release.artists.joined()],
+            );
             vars.insert(
                 TagKey::AlbumArtistSortOrder.to_string(),
-                release.artists.sort_order_joined(),
+                vec![release.artists.sort_order_joined()],
             );
         }
         if let Some(path) = self.path.as_ref() {
-            vars.insert("path".to_string(), path_to_str(path)?);
+            vars.insert("path".to_string(), vec![path_to_str(path)?]);
         }
         if let Some(format) = self.format.as_ref() {
-            vars.insert("format".to_string(), (*format).into());
+            vars.insert("format".to_string(), vec![(*format).into()]);
         }
-        strfmt(template, &vars)
-            .map_err(|e| eyre!(e))
-            .wrap_err(eyre!("Error while formatting track string"))
+        Ok(vars)
     }
 }
 
 impl Format for Release {
-    fn fmt(&self, template: &str) -> Result<String> {
-        let multiple_vars: HashMap<String, Vec<String>> = self.clone().try_into()?;
-        let mut vars: HashMap<String, String> = multiple_vars
-            .into_iter()
-            .map(|(k, v)| (k, v.join(", "))) // TODO
-            .collect();
+    fn vars(&self) -> Result<HashMap<String, Vec<String>>> {
+        let mut vars: HashMap<String, Vec<String>> = self.clone().try_into()?;
         // Multiple value fields are different. The version held in the Track::artists
         // data structure also holds the information for mergining the various
         // artists into a single string. We therefore generate it from the
         // original track instance
-        vars.insert(TagKey::AlbumArtist.to_string(), self.artists.joined());
+        vars.insert(TagKey::AlbumArtist.to_string(), vec![self.artists.joined()]);
         vars.insert(
             TagKey::AlbumArtistSortOrder.to_string(),
-            self.artists.sort_order_joined(),
+            vec![self.artists.sort_order_joined()],
+        );
+        self.primary_type(&mut vars);
+        Ok(vars)
+    }
+}
+
+impl Release {
+    // The release_type tag holds the secondary types too, while paths and
+    // listings only show the primary one
+    fn primary_type(&self, vars: &mut HashMap<String, Vec<String>>) {
+        vars.insert(
+            TagKey::ReleaseType.to_string(),
+            self.release_type.clone().into_iter().collect(),
         );
-        strfmt(template, &vars)
-            .map_err(|e| eyre!(e))
-            .wrap_err(eyre!("Error while formatting release string"))
     }
 }
@@ -1,5 +1,7 @@
+use eyre::{eyre, Result};
 use levenshtein::levenshtein;
 use std::cmp::Ordering;
+use std::fmt::Display;
 use log::debug;
 use pathfinding::kuhn_munkres::kuhn_munkres_min;
 use pathfinding::matrix::Matrix;
@@ -7,11 +9,10 @@ use pathfinding::matrix::Matrix;
 use crate::fetch::structures::Cover;
 use crate::SETTINGS;
 use crate::models::{Track, Release, Artists};
-use crate::settings::ArtProvider;
+use crate::settings::{Art, ArtFormat};
 
 static TRACK_TITLE_FACTOR: usize = 1000;
 static RELEASE_TITLE_FACTOR: usize = 10000;
-static MAX_COVER_SIZE: usize = 5000 * 5000;
 
 fn if_both<T, R>(a: Option<T>, b: Option<T>, then: impl Fn(T, T) -> R) -> Option<R> {
     if let Some(a_val) = a {
@@ -123,52 +124,170 @@ pub fn match_tracks(
     (val+pentality as i64, map)
 }
 
+// The terms a cover is rated on, each normalized between 0 and 1, and their
+// weighted average according to the art.*_relevance settings
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Score {
+    pub total: f64,
+    pub provider: f64,
+    pub matching: f64,
+    pub size: f64,
+    pub squareness: f64,
+    pub format: f64,
+}
+
+impl Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2}: provider {:.2}, match {:.2}, size {:.2}, square {:.2}, format {:.2}",
+            self.total, self.provider, self.matching, self.size, self.squareness, self.format
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct CoverRating(pub f64, pub Cover);
+pub struct CoverRating(pub Score, pub Cover);
 
 impl PartialEq for CoverRating {
     fn eq(&self, other: &Self) -> bool {
-        self.0.eq(&other.0)
+        self.0.total.eq(&other.0.total)
     }
 }
 impl Eq for CoverRating {}
 
 impl PartialOrd for CoverRating {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.0.partial_cmp(&other.0)
+        Some(self.cmp(other))
     }
 }
 impl Ord for CoverRating {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+        self.0
+            .total
+            .partial_cmp(&other.0.total)
+            .unwrap_or(Ordering::Equal)
     }
 }
 
+// Maps val from the [min, max] range to [0, 1], clamping values outside it
+fn normalize(val: f64, min: f64, max: f64) -> f64 {
+    if max <= min {
+        return 1.0;
+    }
+    ((val - min) / (max - min)).clamp(0.0, 1.0)
+}
+
+// How closely the title and artist of the cover match the release, 1 being
+// an exact match regardless of case
+fn match_score(cover: &Cover, release: &Release) -> f64 {
+    let (title, artist) = (cover.title.to_lowercase(), cover.artist.to_lowercase());
+    let (release_title, release_artist) = (
+        release.title.to_lowercase(),
+        release.artists.joined().to_lowercase(),
+    );
+    let longest = title.chars().count().max(release_title.chars().count())
+        + artist.chars().count().max(release_artist.chars().count());
+    let distance = levenshtein(&title, &release_title) + levenshtein(&artist, &release_artist);
+    1.0 - normalize(distance as f64, 0.0, longest as f64)
+}
+
+// Lossless sources and ones already in the output format are preferred, as
+// they don't lose quality when converted
+fn format_score(cover: &Cover, art: &Art) -> f64 {
+    let ext = cover
+        .url
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+    let format = match ext.as_str() {
+        "png" => ArtFormat::Png,
+        "jpg" | "jpeg" => ArtFormat::Jpeg,
+        _ => return 0.5,
+    };
+    if format == ArtFormat::Png || format == art.format {
+        1.0
+    } else {
+        0.0
+    }
+}
 
-fn in_range(val: f64, min: f64, max: f64) -> f64 {
-    val / (max - min)
+// 1 for square images, down to 0 as one side gets much longer than the other
+fn squareness(width: usize, height: usize) -> f64 {
+    match width.max(height) {
+        0 => 0.0,
+        max => width.min(height) as f64 / max as f64,
+    }
 }
 
-fn valuate_cover(levenshtein: f64, cover: &Cover) -> f64 {
-    let art_settings = &SETTINGS.get().unwrap().art;
-    let provider_index = art_settings.providers.iter().position(|p| *p == cover.provider).unwrap();
+// Why a cover of the given size doesn't meet the art.min_width, art.min_height
+// and art.max_aspect_deviation settings, if it doesn't
+pub fn quality_issue(width: usize, height: usize, art: &Art) -> Option<String> {
+    if width < art.min_width as usize || height < art.min_height as usize {
+        return Some(format!("smaller than {}x{}", art.min_width, art.min_height));
+    }
+    let deviation = 1.0 - squareness(width, height);
+    if deviation > art.max_aspect_deviation {
+        return Some(format!("{:.0}% away from square", deviation * 100.0));
+    }
+    None
+}
 
-    in_range(provider_index as f64, 0.0, art_settings.providers.len() as f64) * art_settings.provider_relevance + 
-    levenshtein * art_settings.match_relevance + 
-    in_range((cover.width * cover.height) as f64, 0.0, MAX_COVER_SIZE as f64) * art_settings.size_relevance
+fn valuate_cover(cover: &Cover, release: &Release, art: &Art, target: usize) -> Score {
+    // Providers are listed in order of preference
+    let provider = match art.providers.iter().position(|p| *p == cover.provider) {
+        Some(i) => 1.0 - normalize(i as f64, 0.0, (art.providers.len() - 1) as f64),
+        None => 0.0,
+    };
+    // Anything bigger than the configured size gets scaled down anyway
+    let size = normalize(
+        (cover.width * cover.height) as f64,
+        0.0,
+        target as f64,
+    );
+    let mut score = Score {
+        total: 0.0,
+        provider,
+        matching: match_score(cover, release),
+        size,
+        squareness: squareness(cover.width, cover.height),
+        format: format_score(cover, art),
+    };
+    let weights = art.provider_relevance
+        + art.match_relevance
+        + art.size_relevance
+        + art.squareness_relevance
+        + art.format_relevance;
+    let weighted = score.provider * art.provider_relevance
+        + score.matching * art.match_relevance
+        + score.size * art.size_relevance
+        + score.squareness * art.squareness_relevance
+        + score.format * art.format_relevance;
+    score.total = if weights > 0.0 {
+        weighted / weights
+    } else {
+        0.0
+    };
+    score
 }
 
-pub fn rank_covers(covers_by_provider: Vec<Vec<Cover>>, release: &Release) -> Vec<CoverRating> {
-    let mut vec: Vec<CoverRating> = covers_by_provider.into_iter().flat_map(|covers| covers.into_iter().map(|cover| {
-            let mut distance = 1.0 - ((levenshtein(cover.title.as_str(), release.title.as_str()) + levenshtein(cover.artist.as_str(), release.artists.joined().as_str())) as f64/
-                (cover.title.len().max(release.title.len()) + cover.artist.len().max(release.artists.joined().len())) as f64);
-            if cover.provider == ArtProvider::CoverArtArchive {
-                distance = 0.9; // TODO: better way? otherwise art from the CoverArtArchive always
-                // achieves the best score
-            }
-            CoverRating(valuate_cover(distance, &cover), cover)
-    })).collect();
+// Rates all the covers found, best first
+pub fn rank_covers(
+    covers_by_provider: Vec<Vec<Cover>>,
+    release: &Release,
+) -> Result<Vec<CoverRating>> {
+    let art = &SETTINGS.get().ok_or(eyre!("Could not read settings"))?.art;
+    let covers: Vec<Cover> = covers_by_provider.into_iter().flatten().collect();
+    // Covers kept at full size are measured against the biggest one
+    let target = match (art.width, art.height) {
+        (Some(width), Some(height)) => (width * height) as usize,
+        _ => covers.iter().map(|c| c.width * c.height).max().unwrap_or_default(),
+    };
+    let mut vec: Vec<CoverRating> = covers
+        .into_iter()
+        .map(|cover| CoverRating(valuate_cover(&cover, release, art, target), cover))
+        .collect();
     vec.sort();
     vec.reverse();
-    vec
+    Ok(vec)
 }
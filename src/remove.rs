@@ -0,0 +1,132 @@
+use crate::library::{copies_of, prune, track_filters, Delete, Filter};
+use crate::models::{Format, Track};
+use crate::theme::DialoguerTheme;
+use crate::track::picture::find_art_files;
+use crate::util::remove_empty_dirs;
+use crate::{DB, SETTINGS};
+use dialoguer::Confirm;
+use eyre::{bail, eyre, Result, WrapErr};
+use log::{info, trace, warn};
+use std::collections::HashSet;
+use std::fs::{read_dir, remove_file};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+static FMT: &str = "{album_artist} - {album} - {track_title}";
+
+// Deletes the covers left in an album folder once no other file is there,
+// then removes the folder and its parents if they ended up empty
+pub fn clean_folder<P: AsRef<Path>>(folder: P, root: &Path) -> Result<()> {
+    let folder = folder.as_ref();
+    if !folder.exists() {
+        return Ok(());
+    }
+    let covers = find_art_files(folder)?;
+    let only_covers = read_dir(folder)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .iter()
+        .all(|path| covers.contains(path));
+    if !only_covers {
+        return Ok(());
+    }
+    for cover in covers.iter() {
+        trace!("Removing cover {:?}", cover);
+        remove_file(cover)?;
+    }
+    remove_empty_dirs(folder, root)?;
+    Ok(())
+}
+
+// Deletes the given files from disk, along with the covers and folders they
+// leave behind
+pub fn delete_files(paths: &[PathBuf]) -> Result<()> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut folders: HashSet<PathBuf> = HashSet::new();
+    for path in paths.iter() {
+        trace!("Deleting file {:?}", path);
+        if let Err(e) = remove_file(path) {
+            warn!("Could not delete file {:?}: {}", path, e);
+        }
+        if let Some(parent) = path.parent() {
+            folders.insert(parent.to_path_buf());
+        }
+    }
+    for folder in folders.iter() {
+        clean_folder(folder, &settings.library)
+            .wrap_err(eyre!("Could not clean up folder {:?}", folder))?;
+    }
+    Ok(())
+}
+
+// Drops the given tracks, and whatever is left without tracks, from the
+// library in a single transaction. Files, along with the ones of the copies
+// of the tracks, are deleted once that succeeded
+pub async fn remove_tracks(tracks: &[Track], delete: bool) -> Result<()> {
+    let copies = copies_of(tracks).await?;
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    for track in tracks.iter() {
+        trace!("Removing track {:?}", track);
+        track.delete(&mut tx).await?;
+    }
+    prune(&mut tx).await?;
+    tx.commit().await?;
+
+    if delete {
+        let mut paths = tracks
+            .iter()
+            .map(|t| {
+                t.path
+                    .clone()
+                    .ok_or_else(|| eyre!("Track {:?} has no path", t.mbid))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        paths.extend(copies.into_iter().map(|c| c.path));
+        delete_files(&paths)?;
+    }
+    Ok(())
+}
+
+pub async fn remove(filters: Vec<&String>, delete_files: bool) -> Result<()> {
+    let start = Instant::now();
+    if filters.is_empty() {
+        bail!("Refusing to remove the whole library, at least one filter is required");
+    }
+    let tracks = Track::filter(
+        track_filters(filters)?,
+        vec![" ORDER BY tracks.release, tracks.disc, tracks.number".to_string()],
+    )
+    .await?;
+    if tracks.is_empty() {
+        info!("No track matches the given filters");
+        return Ok(());
+    }
+    for track in tracks.iter() {
+        println!("{}", track.fmt(FMT)?);
+    }
+    let prompt = if delete_files {
+        format!(
+            "Remove these {} tracks from the library and delete their files?",
+            tracks.len()
+        )
+    } else {
+        format!("Remove these {} tracks from the library?", tracks.len())
+    };
+    if !Confirm::with_theme(&DialoguerTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()?
+    {
+        info!("Nothing removed");
+        return Ok(());
+    }
+
+    remove_tracks(&tracks, delete_files).await?;
+    info!(
+        "Removed {} tracks, took {:?}",
+        tracks.len(),
+        start.elapsed()
+    );
+    Ok(())
+}
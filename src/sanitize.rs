@@ -0,0 +1,88 @@
+use crate::settings::{Normalization, Paths};
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::path::PathBuf;
+use unicode_normalization::UnicodeNormalization;
+
+// Turns the values of the fields and the rendered path templates into paths
+// which are safe to use on any filesystem (FAT, exFAT, SMB shares included)
+pub struct Sanitizer<'a> {
+    settings: &'a Paths,
+    rules: Vec<(Regex, &'a str)>,
+}
+
+// Cuts the string to at most max bytes, without splitting characters
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+impl<'a> Sanitizer<'a> {
+    pub fn new(settings: &'a Paths) -> Result<Self> {
+        let rules = settings
+            .replace
+            .iter()
+            .map(|r| {
+                Regex::new(r.pattern.as_str())
+                    .map(|re| (re, r.replacement.as_str()))
+                    .map_err(|e| eyre!("Invalid replacement pattern {:?}: {}", r.pattern, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Sanitizer { settings, rules })
+    }
+
+    fn normalize(&self, s: &str) -> String {
+        match self.settings.normalization {
+            Normalization::None => s.to_string(),
+            Normalization::Nfc => s.nfc().collect(),
+            Normalization::Nfd => s.nfd().collect(),
+        }
+    }
+
+    fn replace(&self, s: &str) -> String {
+        self.rules
+            .iter()
+            .fold(s.to_string(), |s, (re, replacement)| {
+                re.replace_all(s.as_str(), *replacement).to_string()
+            })
+    }
+
+    // Field values are cleaned before being substituted in the template, so
+    // that an artist like AC/DC doesn't introduce a new directory
+    pub fn value(&self, value: &str) -> String {
+        self.replace(self.normalize(value).as_str())
+    }
+
+    fn component(&self, component: &str, max: usize) -> String {
+        let component = self.replace(self.normalize(component).as_str());
+        // Truncating may leave trailing spaces or dots behind
+        let component = self.replace(truncate(component.as_str(), max));
+        match component.is_empty() {
+            true => "_".to_string(),
+            false => component,
+        }
+    }
+
+    // Splits the rendered template in its components, cleaning and
+    // truncating each of them. The extension is preserved when truncating
+    pub fn path(&self, rendered: &str, ext: &str) -> PathBuf {
+        let max = self.settings.max_length;
+        let components = rendered.split('/').collect::<Vec<_>>();
+        let mut path = PathBuf::new();
+        for (i, component) in components.iter().enumerate() {
+            if i < components.len() - 1 {
+                path.push(self.component(component, max));
+            } else {
+                let name = self.component(component, max.saturating_sub(ext.len() + 1));
+                path.push(format!("{}.{}", name, ext));
+            }
+        }
+        path
+    }
+}
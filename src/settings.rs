@@ -1,5 +1,6 @@
 use directories::{ProjectDirs, UserDirs};
 use eyre::{eyre, Result};
+use globset::Glob;
 use image::ImageOutputFormat;
 use log::trace;
 use mime::{Mime, IMAGE_JPEG, IMAGE_PNG};
@@ -8,6 +9,10 @@ use std::fs;
 use std::str::FromStr;
 use std::{fmt::Display, path::PathBuf};
 
+use crate::sanitize::Sanitizer;
+use crate::template::{Condition, Template};
+use crate::track::key::TagKey;
+use crate::track::picture::PictureType;
 use crate::{CLI_NAME, SETTINGS};
 
 static DEFAULT_DB_FILE: &str = "lib.db";
@@ -21,10 +26,16 @@ pub struct Settings {
     #[serde(default = "default_track_name")]
     pub track_name: String,
 
+    #[serde(default)]
+    pub paths: Paths,
+    #[serde(default)]
+    pub import: Import,
     #[serde(default)]
     pub tagging: Tagging,
     #[serde(default)]
     pub art: Art,
+    #[serde(default)]
+    pub duplicates: Duplicates,
 }
 
 fn default_track_name() -> String {
@@ -32,6 +43,195 @@ fn default_track_name() -> String {
                     .to_string()
 }
 
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalization {
+    None,
+    #[default]
+    Nfc,
+    Nfd,
+}
+
+// What to do when a file would be placed where another one already is
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Collision {
+    #[default]
+    Abort,
+    // Appends " (1)", " (2)", ... to the name of the file
+    Rename,
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Replacement {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+// A template used in place of track_name for the releases matching all of
+// the given conditions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathTemplate {
+    // Either the primary or one of the secondary types, i.e. soundtrack
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_type: Option<String>,
+    // Whether the release is credited to Various Artists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compilation: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>,
+    // A condition as in {if:...} templates, i.e. "release_year<1970"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    pub template: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Paths {
+    // Unicode normalization form of the generated paths
+    #[serde(default)]
+    pub normalization: Normalization,
+    // In bytes, for each component of the path, extension included
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    #[serde(default)]
+    pub collisions: Collision,
+    // Checked in order, the first matching one wins over track_name
+    #[serde(default)]
+    pub templates: Vec<PathTemplate>,
+    // Applied in order to the values of the fields and then to each
+    // component of the generated path
+    #[serde(default = "default_replace")]
+    pub replace: Vec<Replacement>,
+}
+
+fn default_max_length() -> usize {
+    255
+}
+
+fn default_replace() -> Vec<Replacement> {
+    vec![
+        (r"[\\/]", "_"),
+        (r"^\.", "_"),
+        (r"[\x00-\x1f]", ""),
+        (r#"[<>:"\?\*\|]"#, "_"),
+        (r"\s+$", ""),
+        (r"^\s+", ""),
+        (r"\.$", "_"),
+    ]
+    .into_iter()
+    .map(|(pattern, replacement)| Replacement {
+        pattern: pattern.to_string(),
+        replacement: replacement.to_string(),
+    })
+    .collect()
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self {
+            normalization: Normalization::default(),
+            max_length: default_max_length(),
+            collisions: Collision::default(),
+            templates: Vec::default(),
+            replace: default_replace(),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportMode {
+    #[default]
+    Copy,
+    Move,
+    Hardlink,
+    Symlink,
+    // Falls back to a regular copy when the filesystem doesn't support it
+    Reflink,
+    // Tags the files where they are, without moving them into the library
+    InPlace,
+}
+
+impl Display for ImportMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportMode::Copy => write!(f, "copy"),
+            ImportMode::Move => write!(f, "move"),
+            ImportMode::Hardlink => write!(f, "hardlink"),
+            ImportMode::Symlink => write!(f, "symlink"),
+            ImportMode::Reflink => write!(f, "reflink"),
+            ImportMode::InPlace => write!(f, "in-place"),
+        }
+    }
+}
+
+impl FromStr for ImportMode {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "copy" => Ok(ImportMode::Copy),
+            "move" => Ok(ImportMode::Move),
+            "hardlink" => Ok(ImportMode::Hardlink),
+            "symlink" => Ok(ImportMode::Symlink),
+            "reflink" => Ok(ImportMode::Reflink),
+            "in-place" => Ok(ImportMode::InPlace),
+            _ => Err(eyre!("Invalid import mode: {}", s)),
+        }
+    }
+}
+
+// Non-audio files brought along with the tracks, matched against their path
+// relative to the imported folder. They keep that relative path inside the
+// album folder, unless a template for it is given
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExtraPattern {
+    Glob(String),
+    Template { glob: String, path: String },
+}
+
+impl ExtraPattern {
+    pub fn glob(&self) -> &str {
+        match self {
+            ExtraPattern::Glob(glob) => glob.as_str(),
+            ExtraPattern::Template { glob, .. } => glob.as_str(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            ExtraPattern::Glob(_) => "{relative_path}",
+            ExtraPattern::Template { path, .. } => path.as_str(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Import {
+    #[serde(default)]
+    pub mode: ImportMode,
+    #[serde(default = "default_extras")]
+    pub extras: Vec<ExtraPattern>,
+}
+
+fn default_extras() -> Vec<ExtraPattern> {
+    vec!["*.log", "*.cue", "*.m3u", "*.m3u8", "*.pdf", "Scans/**"]
+        .into_iter()
+        .map(|glob| ExtraPattern::Glob(glob.to_string()))
+        .collect()
+}
+
+impl Default for Import {
+    fn default() -> Self {
+        Self {
+            mode: ImportMode::default(),
+            extras: default_extras(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Tagging {
     #[serde(default = "default_true")]
@@ -40,6 +240,12 @@ pub struct Tagging {
     pub genre_limit: Option<usize>,
     #[serde(default = "default_true")]
     pub use_original_date: bool,
+    // Tags (either TagKeys or raw keys) which survive a clear
+    #[serde(default = "default_keep")]
+    pub keep: Vec<String>,
+    // Tags (either TagKeys or raw keys) which are never written
+    #[serde(default)]
+    pub drop: Vec<String>,
 
     #[serde(default = "default_id3_separator")]
     pub id3_separator: String,
@@ -53,6 +259,24 @@ fn default_true() -> bool {
     true
 }
 
+fn default_keep() -> Vec<String> {
+    vec![
+        "replay_gain_album_gain",
+        "replay_gain_album_peak",
+        "replay_gain_album_range",
+        "replay_gain_reference_loudness",
+        "replay_gain_track_gain",
+        "replay_gain_track_peak",
+        "replay_gain_track_range",
+        "rating",
+        "comment",
+        "lyrics",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
 fn default_id3_separator() -> String {
     "\0".to_string()
 }
@@ -67,6 +291,8 @@ impl Default for Tagging {
             clear: default_true(),
             genre_limit: Option::default(),
             use_original_date: default_true(),
+            keep: default_keep(),
+            drop: Vec::default(),
             id3_separator: default_id3_separator(),
             mp4_separator: default_separator(),
             ape_separator: default_separator(),
@@ -78,6 +304,15 @@ impl Default for Tagging {
 pub enum ArtProvider {
     CoverArtArchive,
     Itunes,
+    Deezer,
+    // Searched through the autocompletion of the Bandcamp website
+    Bandcamp,
+    // Requires art.discogs_token
+    Discogs,
+    // Requires art.fanart_tv_api_key
+    FanartTv,
+    // Cover files in the imported folder and pictures embedded in the tracks
+    Local,
 }
 
 impl Display for ArtProvider {
@@ -85,6 +320,11 @@ impl Display for ArtProvider {
         match self {
             ArtProvider::CoverArtArchive => write!(f, "CoverArtArchive"),
             ArtProvider::Itunes => write!(f, "iTunes"),
+            ArtProvider::Deezer => write!(f, "Deezer"),
+            ArtProvider::Bandcamp => write!(f, "Bandcamp"),
+            ArtProvider::Discogs => write!(f, "Discogs"),
+            ArtProvider::FanartTv => write!(f, "fanart.tv"),
+            ArtProvider::Local => write!(f, "local files"),
         }
     }
 }
@@ -103,29 +343,164 @@ impl ArtFormat {
             ArtFormat::Jpeg => IMAGE_JPEG,
         }
     }
-}
 
-impl From<ArtFormat> for ImageOutputFormat {
-    fn from(f: ArtFormat) -> Self {
-        match f {
+    pub fn output(&self, quality: u8) -> ImageOutputFormat {
+        match self {
             ArtFormat::Png => ImageOutputFormat::Png,
-            ArtFormat::Jpeg => ImageOutputFormat::Jpeg(100),
+            ArtFormat::Jpeg => ImageOutputFormat::Jpeg(quality),
+        }
+    }
+}
+
+// The size images are scaled down to and how they are encoded, images are
+// kept at their size when none is given. Defaults are the ones of the
+// pictures embedded in the tracks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageSettings {
+    #[serde(default = "default_embedded_size")]
+    pub width: Option<u32>,
+    #[serde(default = "default_embedded_size")]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub format: ArtFormat,
+    // JPEG quality, from 1 to 100
+    #[serde(default = "default_embedded_quality")]
+    pub quality: u8,
+}
+
+fn default_embedded_size() -> Option<u32> {
+    Some(500)
+}
+
+fn default_embedded_quality() -> u8 {
+    85
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        Self {
+            width: default_embedded_size(),
+            height: default_embedded_size(),
+            format: ArtFormat::default(),
+            quality: default_embedded_quality(),
+        }
+    }
+}
+
+// The kinds of images listed by the CoverArtArchive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtType {
+    Front,
+    Back,
+    Booklet,
+    Medium,
+    Tray,
+    Obi,
+    Spine,
+    Liner,
+    Sticker,
+    Poster,
+    Other,
+}
+
+impl Display for ArtType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtType::Front => write!(f, "Front"),
+            ArtType::Back => write!(f, "Back"),
+            ArtType::Booklet => write!(f, "Booklet"),
+            ArtType::Medium => write!(f, "Medium"),
+            ArtType::Tray => write!(f, "Tray"),
+            ArtType::Obi => write!(f, "Obi"),
+            ArtType::Spine => write!(f, "Spine"),
+            ArtType::Liner => write!(f, "Liner"),
+            ArtType::Sticker => write!(f, "Sticker"),
+            ArtType::Poster => write!(f, "Poster"),
+            ArtType::Other => write!(f, "Other"),
+        }
+    }
+}
+
+impl ArtType {
+    // Maps the type names used by the CoverArtArchive, which has a few more
+    pub fn from_caa(name: &str) -> Option<Self> {
+        match name {
+            "Front" => Some(ArtType::Front),
+            "Back" => Some(ArtType::Back),
+            "Booklet" => Some(ArtType::Booklet),
+            "Medium" => Some(ArtType::Medium),
+            "Tray" => Some(ArtType::Tray),
+            "Obi" => Some(ArtType::Obi),
+            "Spine" => Some(ArtType::Spine),
+            "Liner" => Some(ArtType::Liner),
+            "Sticker" => Some(ArtType::Sticker),
+            "Poster" => Some(ArtType::Poster),
+            "Other" => Some(ArtType::Other),
+            _ => None,
+        }
+    }
+
+    pub fn picture_type(&self) -> PictureType {
+        match self {
+            ArtType::Front => PictureType::CoverFront,
+            ArtType::Back => PictureType::CoverBack,
+            ArtType::Booklet | ArtType::Liner => PictureType::Leaflet,
+            ArtType::Medium => PictureType::Media,
+            _ => PictureType::Other,
+        }
+    }
+
+    // The reverse of picture_type, for pictures read from the files
+    pub fn from_picture_type(kind: PictureType) -> Self {
+        match kind {
+            PictureType::CoverFront => ArtType::Front,
+            PictureType::CoverBack => ArtType::Back,
+            PictureType::Leaflet => ArtType::Booklet,
+            PictureType::Media => ArtType::Medium,
+            _ => ArtType::Other,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Art {
+    // Where covers are searched, in order of preference when ranking them
     #[serde(default = "default_art_providers")]
     pub providers: Vec<ArtProvider>,
-    #[serde(default = "default_art_width")]
-    pub width: u32,
-    #[serde(default = "default_art_height")]
-    pub height: u32,
+    // Images saved next to the tracks are kept at full size by default
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    // Covers smaller than this are only used when picked explicitly
+    #[serde(default = "default_art_min_size")]
+    pub min_width: u32,
+    #[serde(default = "default_art_min_size")]
+    pub min_height: u32,
+    // How far from square covers can be, as 1 - shorter side / longer side
+    #[serde(default = "default_max_aspect_deviation")]
+    pub max_aspect_deviation: f64,
+    // Drops the candidates looking like a better ranked one
+    #[serde(default = "default_true")]
+    pub deduplicate: bool,
     #[serde(default)]
     pub format: ArtFormat,
+    #[serde(default = "default_art_quality")]
+    pub quality: u8,
+    // How the pictures embedded in the tracks are sized and encoded, the
+    // settings above applying to the images saved next to them
+    #[serde(default)]
+    pub embedded: ImageSettings,
     #[serde(default = "default_art_image_name")]
     pub image_name: Option<String>,
+    // The images to fetch and save next to the tracks. All but the front
+    // cover come from the CoverArtArchive
+    #[serde(default = "default_art_types")]
+    pub types: Vec<ArtType>,
+    // The fetched images which also get embedded in the tracks
+    #[serde(default = "default_art_types")]
+    pub embed: Vec<ArtType>,
 
     #[serde(default = "default_provider_relevance")]
     pub provider_relevance: f64,
@@ -133,21 +508,79 @@ pub struct Art {
     pub match_relevance: f64,
     #[serde(default = "default_size_relevance")]
     pub size_relevance: f64,
+    #[serde(default = "default_squareness_relevance")]
+    pub squareness_relevance: f64,
+    #[serde(default = "default_format_relevance")]
+    pub format_relevance: f64,
 
     #[serde(default = "default_true")]
     pub cover_art_archive_use_release_group: bool,
+
+    // Where the providers are reached, overridable to point to a mirror
+    #[serde(default = "default_cover_art_archive_url")]
+    pub cover_art_archive_url: String,
+    #[serde(default = "default_itunes_url")]
+    pub itunes_url: String,
+    #[serde(default = "default_deezer_url")]
+    pub deezer_url: String,
+    #[serde(default = "default_bandcamp_url")]
+    pub bandcamp_url: String,
+    #[serde(default = "default_discogs_url")]
+    pub discogs_url: String,
+    #[serde(default = "default_fanart_tv_url")]
+    pub fanart_tv_url: String,
+    #[serde(default)]
+    pub discogs_token: Option<String>,
+    #[serde(default)]
+    pub fanart_tv_api_key: Option<String>,
 }
 
 fn default_art_providers() -> Vec<ArtProvider> {
-    vec![ArtProvider::Itunes, ArtProvider::CoverArtArchive]
+    vec![
+        ArtProvider::CoverArtArchive,
+        ArtProvider::Itunes,
+        ArtProvider::Local,
+    ]
 }
 
-fn default_art_width() -> u32 {
-    1200
+fn default_art_types() -> Vec<ArtType> {
+    vec![ArtType::Front]
 }
 
-fn default_art_height() -> u32 {
-    1200
+fn default_art_quality() -> u8 {
+    100
+}
+
+fn default_art_min_size() -> u32 {
+    500
+}
+
+fn default_max_aspect_deviation() -> f64 {
+    0.1
+}
+
+fn default_cover_art_archive_url() -> String {
+    "http://coverartarchive.org".to_string()
+}
+
+fn default_itunes_url() -> String {
+    "http://itunes.apple.com".to_string()
+}
+
+fn default_deezer_url() -> String {
+    "https://api.deezer.com".to_string()
+}
+
+fn default_bandcamp_url() -> String {
+    "https://bandcamp.com".to_string()
+}
+
+fn default_discogs_url() -> String {
+    "https://api.discogs.com".to_string()
+}
+
+fn default_fanart_tv_url() -> String {
+    "https://webservice.fanart.tv".to_string()
 }
 
 fn default_art_image_name() -> Option<String> {
@@ -166,18 +599,93 @@ fn default_size_relevance() -> f64 {
     4.0 / 8.0
 }
 
+fn default_squareness_relevance() -> f64 {
+    1.0 / 8.0
+}
+
+fn default_format_relevance() -> f64 {
+    1.0 / 8.0
+}
+
 impl Default for Art {
     fn default() -> Self {
         Self {
             providers: default_art_providers(),
-            width: default_art_width(),
-            height: default_art_height(),
+            width: None,
+            height: None,
+            min_width: default_art_min_size(),
+            min_height: default_art_min_size(),
+            max_aspect_deviation: default_max_aspect_deviation(),
+            deduplicate: default_true(),
             format: ArtFormat::default(),
+            quality: default_art_quality(),
+            embedded: ImageSettings::default(),
             image_name: default_art_image_name(),
+            types: default_art_types(),
+            embed: default_art_types(),
             provider_relevance: default_provider_relevance(),
             match_relevance: default_match_relevance(),
             size_relevance: default_size_relevance(),
+            squareness_relevance: default_squareness_relevance(),
+            format_relevance: default_format_relevance(),
             cover_art_archive_use_release_group: default_true(),
+            cover_art_archive_url: default_cover_art_archive_url(),
+            itunes_url: default_itunes_url(),
+            deezer_url: default_deezer_url(),
+            bandcamp_url: default_bandcamp_url(),
+            discogs_url: default_discogs_url(),
+            fanart_tv_url: default_fanart_tv_url(),
+            discogs_token: None,
+            fanart_tv_api_key: None,
+        }
+    }
+}
+
+impl Art {
+    // How the images saved next to the tracks are sized and encoded
+    pub fn on_disk(&self) -> ImageSettings {
+        ImageSettings {
+            width: self.width,
+            height: self.height,
+            format: self.format.clone(),
+            quality: self.quality,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Quality {
+    Format,
+    Bitrate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Duplicates {
+    // Criteria to pick the best copy with, in order of importance
+    #[serde(default = "default_quality")]
+    pub quality: Vec<Quality>,
+    // Formats from the most to the least preferred
+    #[serde(default = "default_formats")]
+    pub formats: Vec<String>,
+}
+
+fn default_quality() -> Vec<Quality> {
+    vec![Quality::Format, Quality::Bitrate]
+}
+
+fn default_formats() -> Vec<String> {
+    vec!["flac", "ape", "mp4", "id3"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+impl Default for Duplicates {
+    fn default() -> Self {
+        Self {
+            quality: default_quality(),
+            formats: default_formats(),
         }
     }
 }
@@ -207,10 +715,73 @@ pub fn load() -> Result<Settings> {
     if set.db == PathBuf::default() {
         set.db = lib.join(DEFAULT_DB_FILE);
     }
+    validate_template("track_name", &set.track_name, TRACK_FIELDS)?;
+    for template in set.paths.templates.iter() {
+        validate_template("paths.templates", &template.template, TRACK_FIELDS)?;
+        if let Some(query) = template.query.as_ref() {
+            let condition = Condition::from_str(query)
+                .map_err(|e| eyre!("Invalid paths.templates query: {}", e))?;
+            validate_field("paths.templates query", condition.field(), TRACK_FIELDS)?;
+        }
+    }
+    for extra in set.import.extras.iter() {
+        Glob::new(extra.glob()).map_err(|e| eyre!("Invalid import.extras glob: {}", e))?;
+        validate_template("import.extras", extra.path(), EXTRA_FIELDS)?;
+    }
+    for (setting, quality) in [
+        ("art.quality", set.art.quality),
+        ("art.embedded.quality", set.art.embedded.quality),
+    ] {
+        if !(1..=100).contains(&quality) {
+            return Err(eyre!("Invalid {}, expected 1 to 100: {}", setting, quality));
+        }
+    }
+    for (provider, setting, key) in [
+        (
+            ArtProvider::Discogs,
+            "art.discogs_token",
+            &set.art.discogs_token,
+        ),
+        (
+            ArtProvider::FanartTv,
+            "art.fanart_tv_api_key",
+            &set.art.fanart_tv_api_key,
+        ),
+    ] {
+        if set.art.providers.contains(&provider) && key.is_none() {
+            return Err(eyre!(
+                "The {} cover provider requires {}",
+                provider,
+                setting
+            ));
+        }
+    }
+    Sanitizer::new(&set.paths)?;
     trace!("Loaded settings: {:?}", set);
     Ok(set)
 }
 
+// Fields available to templates besides the tags
+static TRACK_FIELDS: &[&str] = &["path", "format"];
+static EXTRA_FIELDS: &[&str] = &["filename", "relative_path"];
+
+fn validate_field(setting: &str, field: &str, extra: &[&str]) -> Result<()> {
+    if TagKey::from_str(field).is_err() && !extra.contains(&field) {
+        return Err(eyre!("Unknown field in {}: {}", setting, field));
+    }
+    Ok(())
+}
+
+// Catches mistakes in the path templates before any file gets moved
+fn validate_template(setting: &str, template: &str, extra: &[&str]) -> Result<()> {
+    let template =
+        Template::from_str(template).map_err(|e| eyre!("Invalid {} template: {}", setting, e))?;
+    for field in template.fields() {
+        validate_field(setting, field, extra)?;
+    }
+    Ok(())
+}
+
 pub fn print() -> Result<()> {
     let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
     print!("{}", toml::to_string(settings)?);
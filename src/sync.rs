@@ -0,0 +1,204 @@
+use crate::fetch::get;
+use crate::library::{import_modes, track_filters, Delete, Filter, Store};
+use crate::models::{Extra, Format, Release, Track};
+use crate::settings::ImportMode;
+use crate::track::diff::{diff, report};
+use crate::update::{clean_folders, move_extras, plan_moves, relocate, revert_moves};
+use crate::write::write_track;
+use crate::DB;
+use eyre::{eyre, Result};
+use itertools::Itertools;
+use log::{info, trace, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+static FMT: &str = "{album_artist} - {track_title}";
+// MusicBrainz allows for one request per second on average
+pub static MB_RATE_LIMIT: Duration = Duration::from_secs(1);
+
+fn relocate_all(
+    tracks: &mut [Track],
+    modes: &HashMap<String, ImportMode>,
+    moves: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    let targets = plan_moves(tracks.iter(), modes)?;
+    for (track, target) in tracks.iter_mut().zip(targets) {
+        let path = track
+            .path
+            .clone()
+            .ok_or_else(|| eyre!("Track {:?} has no path", track.mbid))?;
+        let target = match target {
+            Some(t) => t,
+            None => continue,
+        };
+        if let Some(new_path) = relocate(track, target)? {
+            moves.push((path, new_path));
+        }
+    }
+    Ok(())
+}
+
+// Stores the synced tracks, moves the extra files after them and drops the
+// old release if MusicBrainz merged it into another one, in a single
+// transaction. The old release is kept while some of its tracks, which are no
+// longer part of it, still refer to it. Returns the folders the tracks left
+async fn store_release(
+    mbid: &str,
+    release: &Release,
+    tracks: &[Track],
+    stale: usize,
+    extras: &[Extra],
+    moves: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<Vec<PathBuf>> {
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    for track in tracks.iter() {
+        track.store(&mut tx).await?;
+    }
+    if release.mbid.as_deref() != Some(mbid) {
+        warn!("Release {} has been merged into {:?}", mbid, release.mbid);
+        sqlx::query("UPDATE extras SET release = ? WHERE release = ?")
+            .bind(&release.mbid)
+            .bind(mbid)
+            .execute(&mut tx)
+            .await?;
+        if stale == 0 {
+            let mut old = release.clone();
+            old.mbid = Some(mbid.to_string());
+            old.delete(&mut tx).await?;
+        } else {
+            warn!(
+                "Keeping release {} in the library, {} of its tracks are not part of {:?}",
+                mbid, stale, release.mbid
+            );
+        }
+    }
+    let left = move_extras(tracks, extras, moves, &mut tx).await?;
+    tx.commit().await?;
+    Ok(left)
+}
+
+async fn sync_release(
+    mbid: &str,
+    tracks: Vec<Track>,
+    modes: &HashMap<String, ImportMode>,
+    write: bool,
+    relocate_files: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let (release, fresh_tracks) = get(mbid).await?;
+    let fresh_tracks: HashMap<String, Track> = fresh_tracks
+        .into_iter()
+        .filter_map(|t| t.mbid.clone().map(|id| (id, t)))
+        .collect();
+    let mut synced_tracks = vec![];
+    let mut stale = 0;
+    for track in tracks.into_iter() {
+        trace!("Syncing track {:?}", track);
+        let mut synced = match track.mbid.as_ref().and_then(|id| fresh_tracks.get(id)) {
+            Some(t) => t.clone(),
+            None => {
+                warn!(
+                    "Track \"{}\" is no longer part of release {}",
+                    track.fmt(FMT)?,
+                    mbid
+                );
+                stale += 1;
+                continue;
+            }
+        };
+        synced.format = track.format;
+        synced.path = track.path.clone();
+
+        let path = track
+            .path
+            .as_ref()
+            .ok_or_else(|| eyre!("Track {:?} has no path", track.mbid))?;
+        let changes = diff(&track.clone().try_into()?, &synced.clone().try_into()?);
+        if !changes.is_empty() {
+            report(path, path, &changes);
+        }
+        synced_tracks.push(synced);
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    // Extras follow the release they have been merged into
+    let extras = Extra::filter(vec![("release", mbid)], vec![])
+        .await?
+        .into_iter()
+        .map(|mut e| {
+            e.release = release.mbid.clone();
+            e
+        })
+        .collect::<Vec<_>>();
+    let mut moves = vec![];
+    let res = if relocate_files {
+        relocate_all(&mut synced_tracks, modes, &mut moves)
+    } else {
+        Ok(())
+    };
+    let res = match res {
+        Ok(()) => store_release(mbid, &release, &synced_tracks, stale, &extras, &mut moves).await,
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(left) => clean_folders(&left)?,
+        Err(e) => {
+            revert_moves(&moves);
+            return Err(e);
+        }
+    }
+    if write {
+        for synced in synced_tracks.iter() {
+            write_track(synced, false)?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn sync(
+    filters: Vec<&String>,
+    write: bool,
+    relocate_files: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let start = Instant::now();
+    let tracks = Track::filter(
+        track_filters(filters)?,
+        vec![" ORDER BY tracks.release, tracks.disc, tracks.number".to_string()],
+    )
+    .await?;
+    let releases = tracks
+        .into_iter()
+        .filter_map(|t| {
+            t.release
+                .as_ref()
+                .and_then(|r| r.mbid.clone())
+                .map(|id| (id, t))
+        })
+        .into_group_map();
+    let modes = import_modes().await?;
+    info!("Syncing {} releases with MusicBrainz", releases.len());
+    for (i, (mbid, tracks)) in releases.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(MB_RATE_LIMIT).await;
+        }
+        if let Err(e) = sync_release(
+            mbid.as_str(),
+            tracks,
+            &modes,
+            write,
+            relocate_files,
+            dry_run,
+        )
+        .await
+        {
+            warn!("Could not sync release {}: {}", mbid, e);
+        }
+    }
+    info!("Done, took {:?}", start.elapsed());
+    Ok(())
+}
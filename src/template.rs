@@ -0,0 +1,492 @@
+use deunicode::deunicode;
+use eyre::{bail, eyre, Report, Result};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// The template language used for paths and listings. Text is copied as is,
+// while expressions in braces are replaced with values:
+//
+//   {field}            the value of the field, empty when it is missing
+//   {field|default}    a fallback for missing or empty fields
+//   {field:02}         pads the value to the given width, with zeros if the
+//                      width starts with 0 and with spaces otherwise
+//   {fn:fn:field}      applies functions to the value, right to left. Either
+//                      upper, lower, title, first, join(sep), asciify or
+//                      substr(start) and substr(start,length)
+//   {if:cond:body}     renders the body, itself a template, when the
+//                      condition holds. Conditions are either `field`,
+//                      `!field` or `field<op>value` with one of the
+//                      = != < <= > >= operators
+//
+// Multi-valued fields are joined with ", " unless functions say otherwise.
+// A backslash makes the next character literal, as in {album|Unknown\: Live}
+// or \{, and the separator of join is taken whole, so join(, ) works as is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+    If(Condition, Template),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Field {
+    name: String,
+    default: Option<String>,
+    functions: Vec<Function>,
+    width: Option<(usize, char)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Function {
+    Upper,
+    Lower,
+    Title,
+    First,
+    Join(String),
+    Asciify,
+    Substr(usize, Option<usize>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    field: String,
+    test: Test,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Test {
+    Present,
+    Missing,
+    Compare(Op, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Field names which are accepted in place of the tag key they stand for
+static ALIASES: [(&str, &str); 2] = [
+    ("disc_total", "total_discs"),
+    ("track_total", "total_tracks"),
+];
+
+// Splits on the given character, ignoring the escaped ones and the ones
+// nested in braces or parentheses
+fn split_top(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '{' | '(' => depth += 1,
+            '}' | ')' => depth -= 1,
+            c if c == sep && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn parse_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        bail!("Invalid field name: {:?}", name);
+    }
+    let name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, key)| key);
+    Ok(name.to_string())
+}
+
+// Drops the backslashes, keeping the characters they escape
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(chars.next().unwrap_or('\\')),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl FromStr for Function {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, args) = match s.split_once('(') {
+            Some((name, args)) => (name, Some(args.strip_suffix(')').unwrap_or(args))),
+            None => (s, None),
+        };
+        let invalid = || eyre!("Invalid template function: {}", s);
+        match (name, args) {
+            ("upper", None) => Ok(Function::Upper),
+            ("lower", None) => Ok(Function::Lower),
+            ("title", None) => Ok(Function::Title),
+            ("first", None) => Ok(Function::First),
+            ("asciify", None) => Ok(Function::Asciify),
+            ("join", Some(sep)) => Ok(Function::Join(unescape(sep))),
+            ("substr", Some(args)) => match split_top(args, ',')[..] {
+                [start] => Ok(Function::Substr(start.trim().parse()?, None)),
+                [start, len] => Ok(Function::Substr(
+                    start.trim().parse()?,
+                    Some(len.trim().parse()?),
+                )),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(field) = s.strip_prefix('!') {
+            return Ok(Condition {
+                field: parse_name(field)?,
+                test: Test::Missing,
+            });
+        }
+        // Two characters operators go first, so that <= isn't read as <
+        for (token, op) in [
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("=", Op::Eq),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ] {
+            if let Some((field, value)) = s.split_once(token) {
+                return Ok(Condition {
+                    field: parse_name(field)?,
+                    test: Test::Compare(op, unescape(value.trim())),
+                });
+            }
+        }
+        Ok(Condition {
+            field: parse_name(s)?,
+            test: Test::Present,
+        })
+    }
+}
+
+fn parse_expression(expr: &str) -> Result<Part> {
+    let mut segments = split_top(expr, ':');
+    if segments[0] == "if" {
+        if segments.len() < 3 {
+            bail!("Expected {{if:condition:body}}, found {{{}}}", expr);
+        }
+        return Ok(Part::If(
+            segments[1].parse()?,
+            segments[2..].join(":").parse()?,
+        ));
+    }
+    let mut width = None;
+    if segments.len() > 1 {
+        let last = segments[segments.len() - 1];
+        if !last.is_empty() && last.chars().all(|c| c.is_ascii_digit()) {
+            let pad = if last.starts_with('0') { '0' } else { ' ' };
+            width = Some((last.parse()?, pad));
+            segments.pop();
+        }
+    }
+    let spec = segments.pop().ok_or(eyre!("Empty template expression"))?;
+    let (name, default) = match spec.split_once('|') {
+        Some((name, default)) => (name, Some(unescape(default))),
+        None => (spec, None),
+    };
+    Ok(Part::Field(Field {
+        name: parse_name(name)?,
+        default,
+        functions: segments
+            .into_iter()
+            .map(Function::from_str)
+            .collect::<Result<Vec<_>>>()?,
+        width,
+    }))
+}
+
+impl FromStr for Template {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => text.push(chars.next().map_or('\\', |(_, c)| c)),
+                '{' => {
+                    let mut depth = 1;
+                    let mut end = None;
+                    let mut escaped = false;
+                    for (j, c) in chars.by_ref() {
+                        match c {
+                            _ if escaped => escaped = false,
+                            '\\' => escaped = true,
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => {}
+                        }
+                        if depth == 0 {
+                            end = Some(j);
+                            break;
+                        }
+                    }
+                    let end = end.ok_or(eyre!("Unclosed {{ in template: {}", s))?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(parse_expression(&s[i + 1..end])?);
+                }
+                '}' => bail!("Unexpected }} in template: {}", s),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+}
+
+fn title_case(s: &str) -> String {
+    s.split(' ')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Function {
+    fn apply(&self, values: Vec<String>) -> Vec<String> {
+        match self {
+            Function::First => values.into_iter().take(1).collect(),
+            Function::Join(sep) => vec![values.join(sep)],
+            f => values.into_iter().map(|v| f.apply_one(v)).collect(),
+        }
+    }
+
+    fn apply_one(&self, value: String) -> String {
+        match self {
+            Function::Upper => value.to_uppercase(),
+            Function::Lower => value.to_lowercase(),
+            Function::Title => title_case(&value),
+            Function::Asciify => deunicode(&value),
+            Function::Substr(start, len) => {
+                let chars = value.chars().skip(*start);
+                match len {
+                    Some(len) => chars.take(*len).collect(),
+                    None => chars.collect(),
+                }
+            }
+            Function::First | Function::Join(_) => value,
+        }
+    }
+}
+
+fn values<'a>(vars: &'a HashMap<String, Vec<String>>, name: &str) -> Vec<&'a String> {
+    vars.get(name)
+        .map(|v| v.iter().filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+impl Field {
+    fn render(&self, vars: &HashMap<String, Vec<String>>) -> String {
+        let mut vals: Vec<String> = values(vars, &self.name).into_iter().cloned().collect();
+        for function in self.functions.iter().rev() {
+            vals = function.apply(vals);
+        }
+        let value = vals.join(", ");
+        let value = match (value.is_empty(), self.default.as_ref()) {
+            (true, Some(default)) => default.to_string(),
+            _ => value,
+        };
+        match self.width {
+            Some((width, pad)) if !value.is_empty() && value.chars().count() < width => {
+                pad.to_string().repeat(width - value.chars().count()) + value.as_str()
+            }
+            _ => value,
+        }
+    }
+}
+
+impl Condition {
+    pub fn field(&self) -> &str {
+        self.field.as_str()
+    }
+
+    pub fn holds(&self, vars: &HashMap<String, Vec<String>>) -> bool {
+        let vals = values(vars, &self.field);
+        let value = vals.first().map_or("", |v| v.as_str());
+        match &self.test {
+            Test::Present => !vals.is_empty(),
+            Test::Missing => vals.is_empty(),
+            Test::Compare(op, other) => {
+                let ord = match (value.parse::<f64>(), other.parse::<f64>()) {
+                    (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                    _ => value.cmp(other.as_str()),
+                };
+                match op {
+                    Op::Eq => ord == Ordering::Equal,
+                    Op::Ne => ord != Ordering::Equal,
+                    Op::Lt => ord == Ordering::Less,
+                    Op::Le => ord != Ordering::Greater,
+                    Op::Gt => ord == Ordering::Greater,
+                    Op::Ge => ord != Ordering::Less,
+                }
+            }
+        }
+    }
+}
+
+impl Template {
+    pub fn render(&self, vars: &HashMap<String, Vec<String>>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.to_string(),
+                Part::Field(field) => field.render(vars),
+                Part::If(cond, body) if cond.holds(vars) => body.render(vars),
+                Part::If(_, _) => String::new(),
+            })
+            .collect()
+    }
+
+    // All the fields referenced by the template, conditions included
+    pub fn fields(&self) -> Vec<&str> {
+        self.parts
+            .iter()
+            .flat_map(|part| match part {
+                Part::Text(_) => vec![],
+                Part::Field(field) => vec![field.name.as_str()],
+                Part::If(cond, body) => {
+                    let mut fields = vec![cond.field.as_str()];
+                    fields.extend(body.fields());
+                    fields
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, vars: &[(&str, &[&str])]) -> String {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.iter().map(|s| s.to_string()).collect()))
+            .collect();
+        Template::from_str(template).unwrap().render(&vars)
+    }
+
+    #[test]
+    fn join_takes_the_whole_separator() {
+        let artists: &[&str] = &["A", "B"];
+        assert_eq!(render("{join(,):artist}", &[("artist", artists)]), "A,B");
+        assert_eq!(render("{join(, ):artist}", &[("artist", artists)]), "A, B");
+        assert_eq!(
+            render("{join( : ):artist}", &[("artist", artists)]),
+            "A : B"
+        );
+        assert_eq!(render("{join(\\)):artist}", &[("artist", artists)]), "A)B");
+    }
+
+    #[test]
+    fn substr_takes_one_or_two_arguments() {
+        let title: &[&str] = &["Abbey Road"];
+        assert_eq!(render("{substr(6):album}", &[("album", title)]), "Road");
+        assert_eq!(render("{substr(0, 5):album}", &[("album", title)]), "Abbey");
+        assert!(Template::from_str("{substr(0,1,2):album}").is_err());
+        assert!(Template::from_str("{substr(a):album}").is_err());
+    }
+
+    #[test]
+    fn defaults_can_escape_separators() {
+        assert_eq!(render("{album|Unknown\\: Live}", &[]), "Unknown: Live");
+        assert_eq!(render("{album|A, B}", &[]), "A, B");
+        assert_eq!(render("{album|a\\|b}", &[]), "a|b");
+        assert_eq!(render("{upper:album|x\\:y:3}", &[]), "x:y");
+    }
+
+    #[test]
+    fn escapes_work_in_text_and_bodies() {
+        let disc: &[&str] = &["2"];
+        assert_eq!(render("\\{a\\}\\\\", &[]), "{a}\\");
+        assert_eq!(
+            render("{if:disc_number:\\{x\\: y\\}}", &[("disc_number", disc)]),
+            "{x: y}"
+        );
+        assert_eq!(
+            render(
+                "{if:disc_number=2:CD{disc_number}}",
+                &[("disc_number", disc)]
+            ),
+            "CD2"
+        );
+    }
+
+    #[test]
+    fn aliases_stand_for_tag_keys() {
+        let template = Template::from_str("{if:disc_total>1:Disc {disc_number}/}").unwrap();
+        assert_eq!(template.fields(), vec!["total_discs", "disc_number"]);
+        let (discs, disc): (&[&str], &[&str]) = (&["2"], &["1"]);
+        assert_eq!(
+            render(
+                "{if:disc_total>1:Disc {disc_number}/}",
+                &[("total_discs", discs), ("disc_number", disc)]
+            ),
+            "Disc 1/"
+        );
+    }
+
+    #[test]
+    fn padding_and_functions() {
+        let number: &[&str] = &["7"];
+        let artist: &[&str] = &["Björk", "Ólöf"];
+        assert_eq!(
+            render("{track_number:02}", &[("track_number", number)]),
+            "07"
+        );
+        assert_eq!(
+            render("{upper:first:artist}", &[("artist", artist)]),
+            "BJÖRK"
+        );
+        assert_eq!(
+            render("{asciify:artist}", &[("artist", artist)]),
+            "Bjork, Olof"
+        );
+        assert!(Template::from_str("{album").is_err());
+        assert!(Template::from_str("{bad name}").is_err());
+    }
+}
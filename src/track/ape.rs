@@ -8,7 +8,40 @@ use std::path::Path;
 
 use super::format::Format;
 use super::key::TagKey;
-use super::picture::{Picture, PictureType};
+use super::picture::{picture_ext, Picture, PictureType};
+use crate::SETTINGS;
+
+// The keys pictures are stored at, one for each type. Their value is the file
+// name of the picture, a null byte and the image itself
+static PICTURE_KEYS: &[(PictureType, &str)] = &[
+    (PictureType::Other, "Cover Art (Other)"),
+    (PictureType::Icon, "Cover Art (Icon)"),
+    (PictureType::OtherIcon, "Cover Art (Other Icon)"),
+    (PictureType::CoverFront, "Cover Art (Front)"),
+    (PictureType::CoverBack, "Cover Art (Back)"),
+    (PictureType::Leaflet, "Cover Art (Leaflet)"),
+    (PictureType::Media, "Cover Art (Media)"),
+    (PictureType::LeadArtist, "Cover Art (Lead Artist)"),
+    (PictureType::Artist, "Cover Art (Artist)"),
+    (PictureType::Conductor, "Cover Art (Conductor)"),
+    (PictureType::Band, "Cover Art (Band)"),
+    (PictureType::Composer, "Cover Art (Composer)"),
+    (PictureType::Lyricist, "Cover Art (Lyricist)"),
+    (
+        PictureType::RecordingLocation,
+        "Cover Art (Recording Location)",
+    ),
+    (PictureType::DuringRecording, "Cover Art (During Recording)"),
+    (
+        PictureType::DuringPerformance,
+        "Cover Art (During Performance)",
+    ),
+    (PictureType::ScreenCapture, "Cover Art (Video Capture)"),
+    (PictureType::BrightFish, "Cover Art (Fish)"),
+    (PictureType::Illustration, "Cover Art (Illustration)"),
+    (PictureType::BandLogo, "Cover Art (Band Logotype)"),
+    (PictureType::PublisherLogo, "Cover Art (Publisher Logotype)"),
+];
 
 #[derive(Clone)]
 pub struct Tag {
@@ -56,7 +89,7 @@ impl crate::track::Tag for Tag {
         self.set_pictures(vec![])?;
         Ok(())
     }
-    fn separator(&self) -> Format {
+    fn format(&self) -> Format {
         Format::Ape
     }
     fn separator(&self) -> Option<String> {
@@ -73,6 +106,11 @@ impl crate::track::Tag for Tag {
         Ok(())
     }
 
+    fn remove_str(&mut self, key: &str) -> Result<()> {
+        self.tag.remove_item(key);
+        Ok(())
+    }
+
     fn get_all(&self) -> HashMap<String, Vec<String>> {
         let mut out = HashMap::new();
         for item in self.tag.iter() {
@@ -86,26 +124,56 @@ impl crate::track::Tag for Tag {
     fn get_pictures(&self) -> Result<Vec<Picture>> {
         self.tag
             .iter()
-            .filter_map(|item| match &item.value {
-                ItemValue::Binary(b) => Some((item.key.clone(), b)),
-                _ => None,
+            .filter_map(|item| {
+                let (kind, _) = PICTURE_KEYS
+                    .iter()
+                    .find(|(_, key)| key.eq_ignore_ascii_case(&item.key))?;
+                match &item.value {
+                    ItemValue::Binary(b) => Some((*kind, b)),
+                    _ => None,
+                }
             })
-            .map(|item| -> Result<Picture> {
+            .map(|(kind, value)| -> Result<Picture> {
+                let (name, data) = match value.iter().position(|b| *b == 0) {
+                    Some(i) => (
+                        String::from_utf8_lossy(&value[..i]).to_string(),
+                        &value[i + 1..],
+                    ),
+                    None => (String::new(), &value[..]),
+                };
                 Ok(Picture {
-                    mime_type: infer::get(&item.1.to_vec())
+                    mime_type: infer::get(data)
                         .ok_or(eyre!("Could not infer mime type from binary picture"))?
                         .to_string()
                         .parse()?,
-                    picture_type: PictureType::CoverFront,
-                    description: item.0,
-                    data: item.1.to_vec(),
+                    picture_type: kind,
+                    description: Path::new(&name)
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    data: data.to_vec(),
                 })
             })
             .collect::<Result<Vec<_>>>()
     }
 
     fn set_pictures(&mut self, pictures: Vec<Picture>) -> Result<()> {
-        unimplemented!()
+        for (_, key) in PICTURE_KEYS.iter() {
+            self.tag.remove_item(key);
+        }
+        // A single picture fits at the key of each type
+        for pic in pictures {
+            let key = PICTURE_KEYS
+                .iter()
+                .find(|(kind, _)| *kind == pic.picture_type)
+                .map_or("Cover Art (Other)", |(_, key)| *key);
+            let mut value =
+                format!("{}.{}", pic.description, picture_ext(&pic.mime_type)).into_bytes();
+            value.push(0);
+            value.extend(pic.data);
+            self.tag.set_item(Item::from_binary(key, value)?);
+        }
+        Ok(())
     }
 
     fn key_to_str(&self, key: TagKey) -> Vec<&'static str> {
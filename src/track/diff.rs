@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TagChange {
+    Added(String, Vec<String>),
+    Changed(String, Vec<String>, Vec<String>),
+    Removed(String, Vec<String>),
+}
+
+impl TagChange {
+    pub fn key(&self) -> &str {
+        match self {
+            TagChange::Added(k, _) => k.as_str(),
+            TagChange::Changed(k, _, _) => k.as_str(),
+            TagChange::Removed(k, _) => k.as_str(),
+        }
+    }
+}
+
+impl Display for TagChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagChange::Added(k, v) => write!(f, "+ {}: {}", k, v.join(", ")),
+            TagChange::Changed(k, old, new) => {
+                write!(f, "~ {}: {} -> {}", k, old.join(", "), new.join(", "))
+            }
+            TagChange::Removed(k, v) => write!(f, "- {}: {}", k, v.join(", ")),
+        }
+    }
+}
+
+pub fn diff(
+    before: &HashMap<String, Vec<String>>,
+    after: &HashMap<String, Vec<String>>,
+) -> Vec<TagChange> {
+    let mut changes = after
+        .iter()
+        .filter_map(|(k, v)| match before.get(k) {
+            None => Some(TagChange::Added(k.clone(), v.clone())),
+            Some(old) if old != v => Some(TagChange::Changed(k.clone(), old.clone(), v.clone())),
+            _ => None,
+        })
+        .chain(
+            before
+                .iter()
+                .filter(|(k, _)| !after.contains_key(*k))
+                .map(|(k, v)| TagChange::Removed(k.clone(), v.clone())),
+        )
+        .collect::<Vec<_>>();
+    changes.sort_by(|a, b| a.key().cmp(b.key()));
+    changes
+}
+
+pub fn report<P: AsRef<Path>>(from: P, to: P, changes: &[TagChange]) {
+    println!("{}", from.as_ref().display());
+    if from.as_ref() != to.as_ref() {
+        println!("    -> {}", to.as_ref().display());
+    }
+    for change in changes.iter() {
+        println!("    {}", change);
+    }
+}
@@ -2,12 +2,15 @@ use eyre::{bail, eyre, Report, Result, WrapErr};
 use itertools::Itertools;
 use log::warn;
 use std::collections::HashMap;
-use std::fs::copy;
-use std::path::PathBuf;
+use std::fs::{canonicalize, copy, hard_link, metadata, remove_file, rename, File};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
 
 #[cfg(feature = "ape")]
 use super::ape;
+use super::diff::{diff, TagChange};
 #[cfg(feature = "flac")]
 use super::flac;
 use super::format::Format;
@@ -20,6 +23,7 @@ use super::TagFrom;
 use super::{Tag, TagError};
 use crate::models::UNKNOWN_TITLE;
 use crate::models::{Artist, GroupTracks, Release, Track};
+use crate::settings::{ImportMode, Tagging};
 use crate::track::TagKey;
 use crate::util::{dedup, maybe_date};
 
@@ -30,6 +34,29 @@ pub struct TrackFile {
     tag: Box<dyn Tag>,
 }
 
+// Places any file at the given path according to the import mode
+pub fn relocate_file(from: &Path, to: &Path, mode: ImportMode) -> Result<()> {
+    match mode {
+        ImportMode::Copy => {
+            copy(from, to)?;
+        }
+        ImportMode::Move => {
+            // rename fails across filesystems, fall back to copy + delete
+            if rename(from, to).is_err() {
+                copy(from, to)?;
+                remove_file(from)?;
+            }
+        }
+        ImportMode::Hardlink => hard_link(from, to)?,
+        ImportMode::Symlink => std::os::unix::fs::symlink(from, to)?,
+        ImportMode::Reflink => {
+            reflink_copy::reflink_or_copy(from, to)?;
+        }
+        ImportMode::InPlace => {}
+    }
+    Ok(())
+}
+
 impl TrackFile {
     pub fn open(path: &PathBuf) -> Result<TrackFile> {
         let format = Format::from_path(path)
@@ -60,12 +87,20 @@ impl TrackFile {
         self.tag.set_tag(key, values)
     }
 
+    pub fn get_pictures(&self) -> Result<Vec<Picture>> {
+        self.tag.get_pictures()
+    }
     pub fn set_pictures(&mut self, pictures: Vec<Picture>) -> Result<()> {
         self.tag.set_pictures(pictures)
     }
 
-    pub fn duplicate_to(&mut self, path: &PathBuf) -> Result<()> {
-        copy(&self.path, path)?;
+    // Places the file at the given path according to the import mode, and
+    // reads the tags back from the new location. In-place leaves it untouched
+    pub fn relocate(&mut self, path: &Path, mode: ImportMode) -> Result<()> {
+        if mode == ImportMode::InPlace {
+            return Ok(());
+        }
+        relocate_file(&self.path, path, mode)?;
         self.path = path.to_path_buf();
         self.tag = match self.format {
             #[cfg(feature = "flac")]
@@ -81,12 +116,73 @@ impl TrackFile {
         Ok(())
     }
 
+    // Writes the tags to a temporary copy next to the file, which is checked
+    // to still be readable before atomically taking the place of the original
     pub fn write(&mut self) -> Result<()> {
-        self.tag
-            .write_to_path(&self.path)
+        self.write_atomic()
             .wrap_err(format!("Could not write tags to file: {:?}", self.path))
     }
 
+    fn write_atomic(&mut self) -> Result<()> {
+        // Follow symlinks, the tags are meant for the file they point to
+        let target = canonicalize(&self.path)?;
+        let name = target
+            .file_name()
+            .ok_or(eyre!("Invalid file name: {:?}", target))?
+            .to_string_lossy();
+        let tmp = target.with_file_name(format!(".{}.tagger-tmp", name));
+        copy(&target, &tmp)?;
+        let res = self.write_verified(&tmp).and_then(|_| {
+            if metadata(&target)?.nlink() > 1 {
+                // Renaming would unlink the file from its other names, so
+                // overwrite the content in place instead
+                copy(&tmp, &target)?;
+                File::open(&target)?.sync_all()?;
+                remove_file(&tmp)?;
+            } else {
+                rename(&tmp, &target)?;
+            }
+            if let Some(parent) = target.parent() {
+                File::open(parent)?.sync_all()?;
+            }
+            Ok(())
+        });
+        if res.is_err() && tmp.exists() {
+            if let Err(e) = remove_file(&tmp) {
+                warn!("Could not remove temporary file {:?}: {}", tmp, e);
+            }
+        }
+        res
+    }
+
+    fn write_verified(&mut self, tmp: &PathBuf) -> Result<()> {
+        self.tag.write_to_path(tmp)?;
+        File::open(tmp)?.sync_all()?;
+        TrackFile::open(tmp).wrap_err("The written file could not be read back")?;
+        Ok(())
+    }
+
+    pub fn get_all(&self) -> HashMap<String, Vec<String>> {
+        self.tag.get_all()
+    }
+
+    // Replaces all the tags get_all lists with the given raw ones, leaving
+    // the ones it can't represent alone
+    pub fn set_all(&mut self, tags: HashMap<String, Vec<String>>) -> Result<()> {
+        for k in self.tag.get_all().into_keys() {
+            self.tag.remove_str(k.as_str())?;
+        }
+        for (k, v) in tags.into_iter() {
+            self.tag.set_str(k.as_str(), v)?;
+        }
+        Ok(())
+    }
+
+    // Lists the changes in the raw tags needed to go from this file to `other`
+    pub fn diff(&self, other: &TrackFile) -> Vec<TagChange> {
+        diff(&self.tag.get_all(), &other.tag.get_all())
+    }
+
     fn ignore_unsupported(r: Result<(), TagError>) -> Result<()> {
         match r {
             Err(TagError::NotSupported) => Ok(()),
@@ -102,10 +198,47 @@ impl TrackFile {
         Ok(())
     }
 
-    pub fn clear(&mut self) -> Result<()> {
-        self.tag.clear()?;
+    // Resolves a list of keys, either TagKeys (by name) or raw keys of the
+    // underlying format, into the raw keys to be read/written
+    fn raw_keys(&self, keys: &[String]) -> Vec<String> {
+        keys.iter()
+            .flat_map(|k| match TagKey::from_str(k.as_str()) {
+                Ok(key) => self
+                    .tag
+                    .key_to_str(key)
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                Err(_) => vec![k.to_string()],
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self, keep: &[String]) -> Result<()> {
+        let keep = self.raw_keys(keep);
+        self.tag.clear_except(&keep)
+    }
+
+    pub fn remove(&mut self, keys: &[String]) -> Result<()> {
+        for k in self.raw_keys(keys).into_iter() {
+            self.tag.remove_str(k.as_str())?;
+        }
         Ok(())
     }
+
+    pub fn retag(&mut self, tagging: &Tagging, tags: HashMap<TagKey, Vec<String>>) -> Result<()> {
+        if tagging.clear {
+            self.clear(&tagging.keep)?;
+        }
+        self.apply(
+            tags.into_iter()
+                .filter(|(k, _)| !tagging.drop.contains(&k.to_string()))
+                .collect(),
+        )?;
+        // Removing afterwards also gets rid of stale values and of raw keys
+        // some other TagKey may have been mapped onto
+        self.remove(&tagging.drop)
+    }
 }
 
 fn artists_with_name(name: String, sep: Option<String>) -> Vec<Artist> {
@@ -156,6 +289,7 @@ impl TryFrom<TrackFile> for Track {
         let file_singleton = vec![file];
         Ok(Track {
             mbid: first_tag(&file_singleton, TagKey::MusicBrainzTrackID),
+            recording_mbid: first_tag(&file_singleton, TagKey::MusicBrainzRecordingID),
             title: first_tag(&file_singleton, TagKey::TrackTitle)
                 .ok_or(eyre!("A track doesn't have any title"))?,
             artists: artists_from_tag(&file_singleton, TagKey::Artists),
@@ -198,6 +332,11 @@ impl TryFrom<Vec<TrackFile>> for Release {
             v1
         };
 
+        // The order of the values matters here, the primary type goes first
+        let release_types = tracks
+            .first()
+            .map(|t| t.get_tag(TagKey::ReleaseType))
+            .unwrap_or_default();
         Ok(Release {
             mbid: first_tag(&tracks, TagKey::MusicBrainzReleaseID),
             release_group_mbid: first_tag(&tracks, TagKey::MusicBrainzReleaseGroupID),
@@ -211,7 +350,8 @@ impl TryFrom<Vec<TrackFile>> for Release {
             label: first_tag(&tracks, TagKey::RecordLabel),
             catalog_no: first_tag(&tracks, TagKey::CatalogNumber),
             status: first_tag(&tracks, TagKey::ReleaseStatus),
-            release_type: first_tag(&tracks, TagKey::ReleaseType),
+            release_type: release_types.first().cloned(),
+            secondary_types: release_types.into_iter().skip(1).collect(),
             date: maybe_date(
                 first_tag(&tracks, TagKey::ReleaseDate)
                     .or_else(|| first_tag(&tracks, TagKey::ReleaseYear)),
@@ -58,6 +58,11 @@ impl crate::track::Tag for Tag {
         Ok(())
     }
 
+    fn remove_str(&mut self, key: &str) -> Result<()> {
+        self.tag.remove_vorbis(key);
+        Ok(())
+    }
+
     fn get_all(&self) -> HashMap<String, Vec<String>> {
         let mut out = HashMap::new();
         if let Some(vorbis) = self.tag.vorbis_comments() {
@@ -30,6 +30,8 @@ pub struct Tag {
 }
 
 static EXTENDED_LEN_4: [&str; 1] = ["ASIN"];
+// Frames holding more than text, which can only be kept as a whole
+static WHOLE_FRAMES: [&str; 3] = ["COMM", "USLT", "POPM"];
 
 impl super::TagFrom for Tag {
     fn from_path<P>(path: P) -> Result<Box<dyn crate::track::Tag>>
@@ -50,11 +52,32 @@ impl super::TagFrom for Tag {
 
 impl super::Tag for Tag {
     fn clear(&mut self) -> Result<()> {
-        let map = self.get_all();
-        for key in map.keys() {
-            self.tag.remove(key);
+        self.tag = id3::Tag::with_version(self.tag.version());
+        Ok(())
+    }
+    fn clear_except(&mut self, keep: &[String]) -> Result<()> {
+        // Comments, lyrics and ratings are mapped to the frame id followed by
+        // the description, i.e. COMM:description, and get_str can't read them
+        let frames = self
+            .tag
+            .frames()
+            .filter(|f| {
+                WHOLE_FRAMES.contains(&f.id())
+                    && keep.iter().any(|k| k.split(':').next() == Some(f.id()))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let kept = keep
+            .iter()
+            .filter_map(|k| self.get_str(k).map(|v| (k, v)))
+            .collect::<Vec<_>>();
+        self.clear()?;
+        for (k, v) in kept.into_iter() {
+            self.set_str(k, v)?;
+        }
+        for frame in frames.into_iter() {
+            self.tag.add_frame(frame);
         }
-        self.set_pictures(vec![])?;
         Ok(())
     }
     fn format(&self) -> Format {
@@ -97,6 +120,18 @@ impl super::Tag for Tag {
         Ok(())
     }
 
+    fn remove_str(&mut self, key: &str) -> Result<()> {
+        let id = key.split(':').next().unwrap_or(key);
+        if WHOLE_FRAMES.contains(&id) {
+            self.tag.remove(id);
+        } else if key.len() != 4 || EXTENDED_LEN_4.contains(&key) {
+            self.tag.remove_extended_text(Some(key), None);
+        } else {
+            self.tag.remove(key);
+        }
+        Ok(())
+    }
+
     fn get_all(&self) -> HashMap<String, Vec<String>> {
         let mut tags = HashMap::new();
         for frame in self.tag.frames() {
@@ -1,5 +1,7 @@
 #![allow(clippy::upper_case_acronyms)]
+use eyre::{eyre, Report, Result};
 use std::fmt::Display;
+use std::str::FromStr;
 
 // Taken from Music Brainz Picard as a reference:
 // https://picard-docs.musicbrainz.org/en/appendices/tag_mapping.html
@@ -102,104 +104,143 @@ pub enum TagKey {
     Duration,
 }
 
+// The names tag keys go by in the settings and in templates
+static NAMES: [(TagKey, &str); 93] = [
+    (TagKey::AcoustidID, "acoustid_id"),
+    (TagKey::AcoustidIDFingerprint, "acoustid_id_fingerprint"),
+    (TagKey::Album, "album"),
+    (TagKey::AlbumArtist, "album_artist"),
+    (TagKey::AlbumArtistSortOrder, "album_artist_sort_order"),
+    (TagKey::AlbumSortOrder, "album_sort_order"),
+    (TagKey::Arranger, "arranger"),
+    (TagKey::Artist, "artist"),
+    (TagKey::ArtistSortOrder, "artist_sort_order"),
+    (TagKey::Artists, "artists"),
+    (TagKey::ASIN, "asin"),
+    (TagKey::Barcode, "barcode"),
+    (TagKey::BPM, "bpm"),
+    (TagKey::CatalogNumber, "catalog_number"),
+    (TagKey::Comment, "comment"),
+    (TagKey::Compilation, "compilation"),
+    (TagKey::Composer, "composer"),
+    (TagKey::ComposerSortOrder, "composer_sort_order"),
+    (TagKey::Conductor, "conductor"),
+    (TagKey::Copyright, "copyright"),
+    (TagKey::Director, "director"),
+    (TagKey::DiscNumber, "disc_number"),
+    (TagKey::DiscSubtitle, "disc_subtitle"),
+    (TagKey::EncodedBy, "encoded_by"),
+    (TagKey::EncoderSettings, "encoder_settings"),
+    (TagKey::Engineer, "engineer"),
+    (TagKey::GaplessPlayback, "gapless_playback"),
+    (TagKey::Genre, "genre"),
+    (TagKey::Grouping, "grouping"),
+    (TagKey::InitialKey, "initial_key"),
+    (TagKey::ISRC, "isrc"),
+    (TagKey::Language, "language"),
+    (TagKey::License, "license"),
+    (TagKey::Lyricist, "lyricist"),
+    (TagKey::Lyrics, "lyrics"),
+    (TagKey::Media, "media"),
+    (TagKey::MixDJ, "mix_dj"),
+    (TagKey::Mixer, "mixer"),
+    (TagKey::Mood, "mood"),
+    (TagKey::Movement, "movement"),
+    (TagKey::MovementCount, "movement_count"),
+    (TagKey::MovementNumber, "movement_number"),
+    (TagKey::MusicBrainzArtistID, "music_brainz_artist_id"),
+    (TagKey::MusicBrainzDiscID, "music_brainz_disc_id"),
+    (
+        TagKey::MusicBrainzOriginalArtistID,
+        "music_brainz_original_artist_id",
+    ),
+    (
+        TagKey::MusicBrainzOriginalReleaseID,
+        "music_brainz_original_release_id",
+    ),
+    (TagKey::MusicBrainzRecordingID, "music_brainz_recording_id"),
+    (
+        TagKey::MusicBrainzReleaseArtistID,
+        "music_brainz_release_artist_id",
+    ),
+    (
+        TagKey::MusicBrainzReleaseGroupID,
+        "music_brainz_release_group_id",
+    ),
+    (TagKey::MusicBrainzReleaseID, "music_brainz_release_id"),
+    (TagKey::MusicBrainzTrackID, "music_brainz_track_id"),
+    (TagKey::MusicBrainzTRMID, "music_brainz_trmid"),
+    (TagKey::MusicBrainzWorkID, "music_brainz_work_id"),
+    (TagKey::MusicIPFingerprint, "music_ip_fingerprint"),
+    (TagKey::MusicIPPUID, "music_ippuid"),
+    (TagKey::OriginalAlbum, "original_album"),
+    (TagKey::OriginalArtist, "original_artist"),
+    (TagKey::OriginalFilename, "original_filename"),
+    (TagKey::OriginalReleaseDate, "original_release_date"),
+    (TagKey::OriginalReleaseYear, "original_release_year"),
+    (TagKey::Performer, "performer"),
+    (TagKey::Podcast, "podcast"),
+    (TagKey::PodcastURL, "podcast_url"),
+    (TagKey::Producer, "producer"),
+    (TagKey::Rating, "rating"),
+    (TagKey::RecordLabel, "record_label"),
+    (TagKey::ReleaseCountry, "release_country"),
+    (TagKey::ReleaseYear, "release_year"),
+    (TagKey::ReleaseDate, "release_date"),
+    (TagKey::ReleaseStatus, "release_status"),
+    (TagKey::ReleaseType, "release_type"),
+    (TagKey::Remixer, "remixer"),
+    (TagKey::ReplayGainAlbumGain, "replay_gain_album_gain"),
+    (TagKey::ReplayGainAlbumPeak, "replay_gain_album_peak"),
+    (TagKey::ReplayGainAlbumRange, "replay_gain_album_range"),
+    (
+        TagKey::ReplayGainReferenceLoudness,
+        "replay_gain_reference_loudness",
+    ),
+    (TagKey::ReplayGainTrackGain, "replay_gain_track_gain"),
+    (TagKey::ReplayGainTrackPeak, "replay_gain_track_peak"),
+    (TagKey::ReplayGainTrackRange, "replay_gain_track_range"),
+    (TagKey::Script, "script"),
+    (TagKey::ShowName, "show_name"),
+    (TagKey::ShowNameSortOrder, "show_name_sort_order"),
+    (TagKey::ShowWorkAndMovement, "show_work_and_movement"),
+    (TagKey::Subtitle, "subtitle"),
+    (TagKey::TotalDiscs, "total_discs"),
+    (TagKey::TotalTracks, "total_tracks"),
+    (TagKey::TrackNumber, "track_number"),
+    (TagKey::TrackTitle, "track_title"),
+    (TagKey::TrackTitleSortOrder, "track_title_sort_order"),
+    (TagKey::Website, "website"),
+    (TagKey::WorkTitle, "work_title"),
+    (TagKey::Writer, "writer"),
+    // Internal, not mapped from picard
+    (TagKey::Duration, "duration"),
+];
+
+// Misspelled names accepted for the sake of existing configurations
+static OLD_NAMES: [(TagKey, &str); 2] = [
+    (TagKey::Engineer, "engigneer"),
+    (TagKey::OriginalReleaseYear, "original_relese_year"),
+];
+
 impl Display for TagKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            TagKey::AcoustidID => write!(f, "acoustid_id"),
-            TagKey::AcoustidIDFingerprint => write!(f, "acoustid_id_fingerprint"),
-            TagKey::Album => write!(f, "album"),
-            TagKey::AlbumArtist => write!(f, "album_artist"),
-            TagKey::AlbumArtistSortOrder => write!(f, "album_artist_sort_order"),
-            TagKey::AlbumSortOrder => write!(f, "album_sort_order"),
-            TagKey::Arranger => write!(f, "arranger"),
-            TagKey::Artist => write!(f, "artist"),
-            TagKey::ArtistSortOrder => write!(f, "artist_sort_order"),
-            TagKey::Artists => write!(f, "artists"),
-            TagKey::ASIN => write!(f, "asin"),
-            TagKey::Barcode => write!(f, "barcode"),
-            TagKey::BPM => write!(f, "bpm"),
-            TagKey::CatalogNumber => write!(f, "catalog_number"),
-            TagKey::Comment => write!(f, "comment"),
-            TagKey::Compilation => write!(f, "compilation"),
-            TagKey::Composer => write!(f, "composer"),
-            TagKey::ComposerSortOrder => write!(f, "composer_sort_order"),
-            TagKey::Conductor => write!(f, "conductor"),
-            TagKey::Copyright => write!(f, "copyright"),
-            TagKey::Director => write!(f, "director"),
-            TagKey::DiscNumber => write!(f, "disc_number"),
-            TagKey::DiscSubtitle => write!(f, "disc_subtitle"),
-            TagKey::EncodedBy => write!(f, "encoded_by"),
-            TagKey::EncoderSettings => write!(f, "encoder_settings"),
-            TagKey::Engineer => write!(f, "engigneer"),
-            TagKey::GaplessPlayback => write!(f, "gapless_playback"),
-            TagKey::Genre => write!(f, "genre"),
-            TagKey::Grouping => write!(f, "grouping"),
-            TagKey::InitialKey => write!(f, "initial_key"),
-            TagKey::ISRC => write!(f, "isrc"),
-            TagKey::Language => write!(f, "language"),
-            TagKey::License => write!(f, "license"),
-            TagKey::Lyricist => write!(f, "lyricist"),
-            TagKey::Lyrics => write!(f, "lyrics"),
-            TagKey::Media => write!(f, "media"),
-            TagKey::MixDJ => write!(f, "mix_dj"),
-            TagKey::Mixer => write!(f, "mixer"),
-            TagKey::Mood => write!(f, "mood"),
-            TagKey::Movement => write!(f, "movement"),
-            TagKey::MovementCount => write!(f, "movement_count"),
-            TagKey::MovementNumber => write!(f, "movement_number"),
-            TagKey::MusicBrainzArtistID => write!(f, "music_brainz_artist_id"),
-            TagKey::MusicBrainzDiscID => write!(f, "music_brainz_disc_id"),
-            TagKey::MusicBrainzOriginalArtistID => write!(f, "music_brainz_original_artist_id"),
-            TagKey::MusicBrainzOriginalReleaseID => write!(f, "music_brainz_original_release_id"),
-            TagKey::MusicBrainzRecordingID => write!(f, "music_brainz_recording_id"),
-            TagKey::MusicBrainzReleaseArtistID => write!(f, "music_brainz_release_artist_id"),
-            TagKey::MusicBrainzReleaseGroupID => write!(f, "music_brainz_release_group_id"),
-            TagKey::MusicBrainzReleaseID => write!(f, "music_brainz_release_id"),
-            TagKey::MusicBrainzTrackID => write!(f, "music_brainz_track_id"),
-            TagKey::MusicBrainzTRMID => write!(f, "music_brainz_trmid"),
-            TagKey::MusicBrainzWorkID => write!(f, "music_brainz_work_id"),
-            TagKey::MusicIPFingerprint => write!(f, "music_ip_fingerprint"),
-            TagKey::MusicIPPUID => write!(f, "music_ippuid"),
-            TagKey::OriginalAlbum => write!(f, "original_album"),
-            TagKey::OriginalArtist => write!(f, "original_artist"),
-            TagKey::OriginalFilename => write!(f, "original_filename"),
-            TagKey::OriginalReleaseDate => write!(f, "original_release_date"),
-            TagKey::OriginalReleaseYear => write!(f, "original_relese_year"),
-            TagKey::Performer => write!(f, "performer"),
-            TagKey::Podcast => write!(f, "podcast"),
-            TagKey::PodcastURL => write!(f, "podcast_url"),
-            TagKey::Producer => write!(f, "producer"),
-            TagKey::Rating => write!(f, "rating"),
-            TagKey::RecordLabel => write!(f, "record_label"),
-            TagKey::ReleaseCountry => write!(f, "release_country"),
-            TagKey::ReleaseYear => write!(f, "release_year"),
-            TagKey::ReleaseDate => write!(f, "release_date"),
-            TagKey::ReleaseStatus => write!(f, "release_status"),
-            TagKey::ReleaseType => write!(f, "release_type"),
-            TagKey::Remixer => write!(f, "remixer"),
-            TagKey::ReplayGainAlbumGain => write!(f, "replay_gain_album_gain"),
-            TagKey::ReplayGainAlbumPeak => write!(f, "replay_gain_album_peak"),
-            TagKey::ReplayGainAlbumRange => write!(f, "replay_gain_album_range"),
-            TagKey::ReplayGainReferenceLoudness => write!(f, "replay_gain_reference_loudness"),
-            TagKey::ReplayGainTrackGain => write!(f, "replay_gain_track_gain"),
-            TagKey::ReplayGainTrackPeak => write!(f, "replay_gain_track_peak"),
-            TagKey::ReplayGainTrackRange => write!(f, "replay_gain_track_range"),
-            TagKey::Script => write!(f, "script"),
-            TagKey::ShowName => write!(f, "show_name"),
-            TagKey::ShowNameSortOrder => write!(f, "show_name_sort_order"),
-            TagKey::ShowWorkAndMovement => write!(f, "show_work_and_movement"),
-            TagKey::Subtitle => write!(f, "subtitle"),
-            TagKey::TotalDiscs => write!(f, "total_discs"),
-            TagKey::TotalTracks => write!(f, "total_tracks"),
-            TagKey::TrackNumber => write!(f, "track_number"),
-            TagKey::TrackTitle => write!(f, "track_title"),
-            TagKey::TrackTitleSortOrder => write!(f, "track_title_sort_order"),
-            TagKey::Website => write!(f, "website"),
-            TagKey::WorkTitle => write!(f, "work_title"),
-            TagKey::Writer => write!(f, "writer"),
+        let (_, name) = NAMES
+            .iter()
+            .find(|(key, _)| key == self)
+            .ok_or(std::fmt::Error)?;
+        write!(f, "{}", name)
+    }
+}
 
-            // Internal, not mapped from picard
-            TagKey::Duration => write!(f, "duration"),
-        }
+impl FromStr for TagKey {
+    type Err = Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NAMES
+            .iter()
+            .chain(OLD_NAMES.iter())
+            .find(|(_, name)| *name == s)
+            .map(|(key, _)| *key)
+            .ok_or_else(|| eyre!("Unknown tag key: {}", s))
     }
 }
@@ -14,6 +14,9 @@ impl TryFrom<Track> for HashMap<TagKey, Vec<String>> {
         if let Some(id) = track.mbid {
             map.insert(TagKey::MusicBrainzTrackID, vec![id]);
         }
+        if let Some(id) = track.recording_mbid {
+            map.insert(TagKey::MusicBrainzRecordingID, vec![id]);
+        }
         if let Some(release) = track.release {
             let rel_map: HashMap<_, _> = release.try_into()?;
             map.extend(rel_map);
@@ -96,8 +99,15 @@ impl TryFrom<Release> for HashMap<TagKey, Vec<String>> {
         if let Some(rel_status) = &release.status {
             map.insert(TagKey::ReleaseStatus, vec![rel_status.to_string()]);
         }
-        if let Some(rel_type) = &release.release_type {
-            map.insert(TagKey::ReleaseType, vec![rel_type.to_string()]);
+        // Like Picard, the primary type is followed by the secondary ones
+        let rel_types: Vec<String> = release
+            .release_type
+            .iter()
+            .chain(release.secondary_types.iter())
+            .cloned()
+            .collect();
+        if !rel_types.is_empty() {
+            map.insert(TagKey::ReleaseType, rel_types);
         }
         if let Some(rel_date) = &release.date {
             map.insert(TagKey::ReleaseDate, vec![rel_date.to_string()]);
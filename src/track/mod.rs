@@ -7,6 +7,7 @@ pub mod id3;
 #[cfg(feature = "mp4")]
 pub mod mp4;
 
+pub mod diff;
 pub mod file;
 pub mod format;
 pub mod key;
@@ -70,12 +71,25 @@ pub trait Tag: TagClone {
     fn separator(&self) -> Option<String>;
 
     fn clear(&mut self) -> Result<()>;
+    // Removes all tags but the ones under the given raw keys
+    fn clear_except(&mut self, keep: &[String]) -> Result<()> {
+        let kept = keep
+            .iter()
+            .filter_map(|k| self.get_str(k).map(|v| (k, v)))
+            .collect::<Vec<_>>();
+        self.clear()?;
+        for (k, v) in kept.into_iter() {
+            self.set_str(k, v)?;
+        }
+        Ok(())
+    }
     fn get_all(&self) -> HashMap<String, Vec<String>>;
     fn get_pictures(&self) -> Result<Vec<Picture>>;
     fn set_pictures(&mut self, pictures: Vec<Picture>) -> Result<()>;
 
     fn get_str(&self, key: &str) -> Option<Vec<String>>;
     fn set_str(&mut self, key: &str, values: Vec<String>) -> Result<()>;
+    fn remove_str(&mut self, key: &str) -> Result<()>;
     fn key_to_str(&self, key: TagKey) -> Vec<&'static str>;
     fn get_tag(&self, key: TagKey) -> Vec<String> {
         let keystrs = self.key_to_str(key);
@@ -1,7 +1,7 @@
 extern crate mp4ameta;
 
 use core::convert::AsRef;
-use eyre::{bail, eyre, Result};
+use eyre::{eyre, Result};
 use mime::{BMP, IMAGE_BMP, IMAGE_JPEG, IMAGE_PNG, JPEG, PNG};
 use mp4ameta::ident::DataIdent;
 use mp4ameta::{Data, ImgFmt};
@@ -113,6 +113,11 @@ impl crate::track::Tag for Tag {
         Ok(())
     }
 
+    fn remove_str(&mut self, key: &str) -> Result<()> {
+        self.tag.remove_data_of(&str_to_ident(key));
+        Ok(())
+    }
+
     fn get_all(&self) -> HashMap<String, Vec<String>> {
         let mut out = HashMap::new();
 
@@ -156,8 +161,9 @@ impl crate::track::Tag for Tag {
             self.tag.remove_data_of(ident);
         }
         for pic in pictures {
+            // mp4 only supports cover front art
             if pic.picture_type != PictureType::CoverFront {
-                bail!("mp4 only supports cover front art");
+                continue;
             }
             let data = match pic.mime_type.subtype() {
                 PNG => Ok(Data::Png(pic.data)),
@@ -1,10 +1,17 @@
+use crate::settings::{ArtFormat, ArtType, ImageSettings};
 use crate::SETTINGS;
 use eyre::eyre;
-use eyre::{bail, Result};
-use mime::Mime;
-use std::fs::write;
+use eyre::Result;
+use image::imageops::FilterType;
+use image::{io::Reader as ImageReader, DynamicImage};
+use log::{trace, warn};
+use mime::{Mime, IMAGE_JPEG, IMAGE_PNG};
+use std::collections::HashMap;
+use std::fs::{read, read_dir, write};
+use std::io::Cursor;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Instant;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PictureType {
@@ -31,6 +38,46 @@ pub enum PictureType {
     PublisherLogo,
 }
 
+// Numbered as in the ID3v2 and FLAC specifications
+static PICTURE_TYPES: &[PictureType] = &[
+    PictureType::Other,
+    PictureType::Icon,
+    PictureType::OtherIcon,
+    PictureType::CoverFront,
+    PictureType::CoverBack,
+    PictureType::Leaflet,
+    PictureType::Media,
+    PictureType::LeadArtist,
+    PictureType::Artist,
+    PictureType::Conductor,
+    PictureType::Band,
+    PictureType::Composer,
+    PictureType::Lyricist,
+    PictureType::RecordingLocation,
+    PictureType::DuringRecording,
+    PictureType::DuringPerformance,
+    PictureType::ScreenCapture,
+    PictureType::BrightFish,
+    PictureType::Illustration,
+    PictureType::BandLogo,
+    PictureType::PublisherLogo,
+];
+
+impl From<PictureType> for u8 {
+    fn from(kind: PictureType) -> Self {
+        kind as u8
+    }
+}
+
+impl From<u8> for PictureType {
+    fn from(n: u8) -> Self {
+        PICTURE_TYPES
+            .get(n as usize)
+            .copied()
+            .unwrap_or(PictureType::Other)
+    }
+}
+
 #[derive(Clone)]
 pub struct Picture {
     pub mime_type: Mime,
@@ -49,7 +96,105 @@ impl std::fmt::Debug for Picture {
     }
 }
 
-pub fn write_picture<P>(picture: &Picture, root: P) -> Result<()>
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    Ok(ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .decode()?)
+}
+
+// Scales the image down to fit the given size, keeping its proportions, and
+// encodes it in the given format
+pub fn encode(img: &DynamicImage, kind: ArtType, settings: &ImageSettings) -> Result<Picture> {
+    let start = Instant::now();
+    let width = settings.width.unwrap_or(img.width());
+    let height = settings.height.unwrap_or(img.height());
+    let resized = if width < img.width() || height < img.height() {
+        img.resize(width, height, FilterType::Gaussian)
+    } else {
+        img.clone()
+    };
+    // JPEG has no alpha channel
+    let resized = match settings.format {
+        ArtFormat::Jpeg => DynamicImage::ImageRgb8(resized.to_rgb8()),
+        ArtFormat::Png => resized,
+    };
+    let mut data: Vec<u8> = Vec::new();
+    resized.write_to(
+        &mut Cursor::new(&mut data),
+        settings.format.output(settings.quality),
+    )?;
+    trace!(
+        "Encoding of art took {:?} (from {}x{} to {}x{}, {} bytes)",
+        start.elapsed(),
+        img.width(),
+        img.height(),
+        resized.width(),
+        resized.height(),
+        data.len()
+    );
+    Ok(Picture {
+        mime_type: settings.format.mime(),
+        picture_type: kind.picture_type(),
+        description: kind.to_string(),
+        data,
+    })
+}
+
+// The names the images of an album are saved with: image_name for the front
+// cover and the lowercase type for the others, numbered when the album has
+// more than one of them, like booklet-01
+pub fn picture_names(types: &[ArtType], image_name: &str) -> Vec<String> {
+    let mut counts: HashMap<ArtType, usize> = HashMap::new();
+    for t in types.iter() {
+        *counts.entry(*t).or_default() += 1;
+    }
+    let mut seen: HashMap<ArtType, usize> = HashMap::new();
+    types
+        .iter()
+        .map(|t| {
+            let n = seen.entry(*t).or_default();
+            *n += 1;
+            match (t, counts[t]) {
+                (ArtType::Front, _) => image_name.to_string(),
+                (_, 1) => t.to_string().to_lowercase(),
+                _ => format!("{}-{:02}", t.to_string().to_lowercase(), n),
+            }
+        })
+        .collect()
+}
+
+// The extension images of the given type are saved with
+pub fn picture_ext(mime: &Mime) -> &str {
+    match mime.subtype() {
+        mime::JPEG => "jpg",
+        _ => mime.subtype().as_str(),
+    }
+}
+
+// Writes the picture in the given folder with the given name, returning its
+// path. Nothing is written when no image_name is configured
+pub fn write_picture<P>(picture: &Picture, root: P, name: &str) -> Result<Option<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let cover_name = &SETTINGS
+        .get()
+        .ok_or(eyre!("Could not read settings"))?
+        .art
+        .image_name;
+    if cover_name.is_none() {
+        return Ok(None);
+    }
+    let name = name.to_string();
+    let ext = picture_ext(&picture.mime_type);
+    let filename = PathBuf::from_str((name + "." + ext).as_str())?;
+    let path = root.as_ref().join(filename);
+    write(&path, &picture.data).map_err(|e| eyre!(e))?;
+    Ok(Some(path))
+}
+
+// Lists the covers written by write_picture which exist in the given folder
+pub fn find_pictures<P>(root: P) -> Result<Vec<(PathBuf, Mime)>>
 where
     P: AsRef<Path>,
 {
@@ -60,10 +205,113 @@ where
         .image_name;
     let name = match cover_name {
         Some(n) => n.to_string(),
-        None => bail!("Picture write not required"),
+        None => return Ok(vec![]),
     };
-    let ext = picture.mime_type.subtype().as_str();
-    let filename = PathBuf::from_str((name + "." + ext).as_str())?;
-    let path = root.as_ref().join(filename);
-    write(path, &picture.data).map_err(|e| eyre!(e))
+    // Covers used to be saved as .jpeg
+    Ok([
+        ("jpg", IMAGE_JPEG),
+        ("jpeg", IMAGE_JPEG),
+        ("png", IMAGE_PNG),
+    ]
+    .into_iter()
+    .map(|(ext, mime)| (root.as_ref().join(format!("{}.{}", name, ext)), mime))
+    .filter(|(path, _)| path.exists())
+    .collect())
+}
+
+// Lists the images of the given type written by write_picture in the given
+// folder, in the order they were numbered
+fn find_art<P>(root: P, kind: ArtType) -> Result<Vec<(PathBuf, Mime)>>
+where
+    P: AsRef<Path>,
+{
+    if kind == ArtType::Front {
+        return Ok(find_pictures(root)?.into_iter().take(1).collect());
+    }
+    let name = kind.to_string().to_lowercase();
+    let mut found = vec![];
+    for entry in read_dir(root)? {
+        let path = entry?.path();
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let numbered = stem
+            .strip_prefix(&format!("{}-", name))
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+        if stem != name && !numbered {
+            continue;
+        }
+        let mime = match path.extension().and_then(|e| e.to_str()) {
+            Some("jpg") | Some("jpeg") => IMAGE_JPEG,
+            Some("png") => IMAGE_PNG,
+            _ => continue,
+        };
+        found.push((path, mime));
+    }
+    found.sort();
+    Ok(found)
+}
+
+// Lists all the images written by write_picture in the given folder, for
+// the types listed in the art.types setting and the front cover
+pub fn find_art_files<P>(root: P) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut files = find_pictures(root.as_ref())?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    for kind in settings.art.types.iter().filter(|t| **t != ArtType::Front) {
+        files.extend(
+            find_art(root.as_ref(), *kind)?
+                .into_iter()
+                .map(|(path, _)| path),
+        );
+    }
+    Ok(files)
+}
+
+// Keeps the first picture of each type. ID3 tags can't hold more, and the
+// other formats follow so that all files end up with the same pictures
+pub fn one_per_type(pictures: Vec<Picture>) -> Vec<Picture> {
+    let mut kept: Vec<Picture> = vec![];
+    for picture in pictures.into_iter() {
+        if kept.iter().any(|p| p.picture_type == picture.picture_type) {
+            warn!(
+                "Not embedding the {} picture, only one of type {:?} can be embedded",
+                picture.description, picture.picture_type
+            );
+            continue;
+        }
+        kept.push(picture);
+    }
+    kept
+}
+
+// Reads back the images written by write_picture in the given folder which
+// are to be embedded, according to the art.embed setting, sized for embedding.
+// Only the first picture of each type is kept, see one_per_type
+pub fn read_pictures<P>(root: P) -> Result<Vec<Picture>>
+where
+    P: AsRef<Path>,
+{
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut pictures = vec![];
+    for kind in settings.art.embed.iter() {
+        for (path, _) in find_art(root.as_ref(), *kind)? {
+            let img = decode(&read(path)?)?;
+            pictures.push(encode(&img, *kind, &settings.art.embedded)?);
+        }
+    }
+    Ok(one_per_type(pictures))
+}
+
+pub fn same_pictures(a: &[Picture], b: &[Picture]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .all(|(x, y)| x.picture_type == y.picture_type && x.data == y.data)
 }
@@ -1,46 +1,261 @@
-use crate::library::{Delete, Filter, LibraryTrack, Store};
-use crate::models::{Format, Track};
-use crate::util::mkdirp;
+use crate::collision::resolve;
+use crate::library::{import_modes, Delete, Filter, LibraryTrack, Store};
+use crate::models::{Extra, Format, Track};
+use crate::settings::ImportMode;
+use crate::track::diff::report;
+use crate::track::file::TrackFile;
+use crate::track::picture::find_pictures;
+use crate::util::{mkdirp, remove_empty_dirs};
+use crate::{DB, SETTINGS};
 use eyre::{eyre, Result};
-use log::{info, trace, warn};
-use std::path::Path;
+use log::{error, info, trace, warn};
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Instant;
 
 static FMT: &str = "{album_artist} - {track_title}";
 
-pub async fn update(_filters: Vec<&String>) -> Result<()> {
-    let start = Instant::now();
-    let mut tracks = Track::filter::<String, String>(
-        vec![],
-        vec![" ORDER BY tracks.release, tracks.disc, tracks.number".to_string()],
-    )
-    .await?;
+// Where the file of the given track should be: files imported in place never
+// move, in all other modes the library owns the file (or link) and it follows
+// the current path template. Tracks missing from the import journal are
+// assumed to have been imported with the configured mode
+fn target_path(track: &Track, modes: &HashMap<String, ImportMode>) -> Result<PathBuf> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mode = track
+        .mbid
+        .as_ref()
+        .and_then(|mbid| modes.get(mbid))
+        .unwrap_or(&settings.import.mode);
+    match mode {
+        ImportMode::InPlace => track
+            .path
+            .clone()
+            .ok_or_else(|| eyre!("Track {:?} has no path", track.mbid)),
+        _ => track.path(),
+    }
+}
+
+// Computes where the files of the given tracks belong, checking for
+// collisions before any of them is moved. Skipped tracks are None
+pub fn plan_moves<'a, I>(
+    tracks: I,
+    modes: &HashMap<String, ImportMode>,
+) -> Result<Vec<Option<PathBuf>>>
+where
+    I: Iterator<Item = &'a Track>,
+{
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let batch = tracks
+        .map(|t| Ok((t.path.clone(), target_path(t, modes)?)))
+        .collect::<Result<Vec<_>>>()?;
+    resolve(&batch, &[], settings.paths.collisions)
+}
+
+// Moves the file of the given track to the given path, as computed by
+// plan_moves. Returns the new path if the file had to be moved
+pub fn relocate(track: &mut Track, new_path: PathBuf) -> Result<Option<PathBuf>> {
+    let path = track
+        .path
+        .clone()
+        .ok_or_else(|| eyre!("Track {:?} has no path", track.mbid))?;
+    if path == new_path {
+        return Ok(None);
+    }
+    warn!("Moving track \"{}\" to {:?}", track.fmt(FMT)?, new_path);
+    if let Some(parent) = new_path.parent() {
+        mkdirp(parent)?;
+    }
+    std::fs::rename(path, &new_path)?;
+    track.path = Some(new_path.clone());
+    Ok(Some(new_path))
+}
+
+// Moves the covers and extra files of the releases whose album folder changed
+// after their tracks were relocated. Covers written before extras were
+// tracked are picked up as well. Returns the folders the tracks left
+pub async fn move_extras(
+    tracks: &[Track],
+    extras: &[Extra],
+    moves: &mut Vec<(PathBuf, PathBuf)>,
+    conn: &mut SqliteConnection,
+) -> Result<Vec<PathBuf>> {
+    // Where the tracks of each release and folder went, by number of tracks
+    let mut folders: HashMap<(Option<String>, PathBuf), HashMap<PathBuf, usize>> = HashMap::new();
+    for (from, to) in moves.iter() {
+        let release = tracks
+            .iter()
+            .find(|t| t.path.as_ref() == Some(to))
+            .and_then(|t| t.release.as_ref())
+            .and_then(|r| r.mbid.clone());
+        if let (Some(from), Some(to)) = (from.parent(), to.parent()) {
+            *folders
+                .entry((release, from.to_path_buf()))
+                .or_default()
+                .entry(to.to_path_buf())
+                .or_default() += 1;
+        }
+    }
+
+    let mut left = vec![];
+    for ((release, from), targets) in folders.into_iter() {
+        left.push(from.clone());
+        let to = match targets.into_iter().max_by_key(|(_, n)| *n) {
+            Some((to, _)) if to != from => to,
+            _ => continue,
+        };
+        let mut files = extras
+            .iter()
+            .filter(|e| e.release == release && e.path.starts_with(&from))
+            .cloned()
+            .collect::<Vec<_>>();
+        // Untracked covers are only claimed once no track is left behind
+        let occupied = tracks
+            .iter()
+            .filter_map(|t| t.path.as_ref())
+            .any(|p| p.parent() == Some(from.as_path()) && p.exists());
+        if !occupied {
+            for (path, _) in find_pictures(&from)? {
+                if !extras.iter().any(|e| e.path == path) {
+                    files.push(Extra {
+                        path,
+                        release: release.clone(),
+                    });
+                }
+            }
+        }
+        for extra in files.into_iter() {
+            if !extra.path.exists() {
+                extra.delete(&mut *conn).await?;
+                continue;
+            }
+            let dest = to.join(extra.path.strip_prefix(&from)?);
+            if dest.exists() {
+                warn!("Not moving {:?}, {:?} already exists", extra.path, dest);
+                continue;
+            }
+            warn!("Moving {:?} to {:?}", extra.path, dest);
+            if let Some(parent) = dest.parent() {
+                mkdirp(parent)?;
+            }
+            std::fs::rename(&extra.path, &dest)?;
+            moves.push((extra.path.clone(), dest.clone()));
+            extra.delete(&mut *conn).await?;
+            Extra {
+                path: dest,
+                release: extra.release.clone(),
+            }
+            .store(&mut *conn)
+            .await?;
+        }
+    }
+    left.sort();
+    left.dedup();
+    Ok(left)
+}
+
+// Removes the folders left empty once albums moved elsewhere
+pub fn clean_folders(folders: &[PathBuf]) -> Result<()> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    for folder in folders.iter() {
+        if let Err(e) = remove_empty_dirs(folder, &settings.library) {
+            warn!("Could not clean up folder {:?}: {}", folder, e);
+        }
+    }
+    Ok(())
+}
+
+// Puts back the files moved by relocate, for when the library changes they
+// belong to could not be committed
+pub fn revert_moves(moves: &[(PathBuf, PathBuf)]) {
+    for (from, to) in moves.iter().rev() {
+        warn!("Moving {:?} back to {:?}", to, from);
+        if let Err(e) = std::fs::rename(to, from) {
+            error!("Could not move {:?} back to {:?}: {}", to, from, e);
+        }
+    }
+}
+
+async fn update_tracks(
+    tracks: &mut [Track],
+    extras: &[Extra],
+    modes: &HashMap<String, ImportMode>,
+    conn: &mut SqliteConnection,
+    moves: &mut Vec<(PathBuf, PathBuf)>,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let mut present = vec![];
     for track in tracks.iter_mut() {
         trace!("Checking track {:?}", track);
-        let mut deleted = false;
-        let mut updated = false;
         let path = track
             .path
-            .as_ref()
+            .clone()
             .ok_or_else(|| eyre!("Track {:?} has no path", track.mbid))?;
-        if !Path::new(path.as_os_str()).exists() {
+        if !path.exists() {
             warn!("Track \"{}\" has been deleted", track.fmt(FMT)?);
-            track.delete().await?;
-            deleted = true;
+            if !dry_run {
+                track.delete(&mut *conn).await?;
+            }
+            continue;
         }
-        let new_path = track.path()?;
-        if path != &new_path && !deleted {
-            warn!("Moving track \"{}\" to {:?}", track.fmt(FMT)?, new_path);
-            if let Some(parent) = new_path.parent() {
-                mkdirp(parent)?;
+        present.push((path, track));
+    }
+    let targets = plan_moves(present.iter().map(|(_, t)| &**t), modes)?;
+    for ((path, track), target) in present.into_iter().zip(targets) {
+        let new_path = match target {
+            Some(p) => p,
+            None => continue,
+        };
+        let file = TrackFile::open(&path)?;
+        let mut retagged = file.clone();
+        retagged.retag(&settings.tagging, track.clone().try_into()?)?;
+        let changes = file.diff(&retagged);
+        if dry_run {
+            if path != new_path || !changes.is_empty() {
+                report(&path, &new_path, &changes);
             }
-            std::fs::rename(path, &new_path)?;
-            track.path = Some(new_path);
-            updated = true;
-            // TODO move covers when album folders change
+            continue;
+        }
+        // Tags are written in place, before the file is moved
+        if !changes.is_empty() {
+            warn!("Retagging track \"{}\"", track.fmt(FMT)?);
+            retagged.write()?;
         }
-        if updated {
-            track.store().await?;
+        if let Some(new_path) = relocate(track, new_path)? {
+            moves.push((path, new_path));
+            track.store(&mut *conn).await?;
+        }
+    }
+    if dry_run {
+        return Ok(vec![]);
+    }
+    move_extras(tracks, extras, moves, conn).await
+}
+
+pub async fn update(_filters: Vec<&String>, dry_run: bool) -> Result<()> {
+    let start = Instant::now();
+    let mut tracks = Track::filter::<String, String>(
+        vec![],
+        vec![" ORDER BY tracks.release, tracks.disc, tracks.number".to_string()],
+    )
+    .await?;
+    let extras = Extra::filter::<String, String>(vec![], vec![]).await?;
+    let modes = import_modes().await?;
+    // The whole batch is applied at once, moving files back if it fails
+    let db = DB.get().ok_or(eyre!("Could not get database"))?;
+    let mut tx = db.begin().await?;
+    let mut moves = vec![];
+    let res = match update_tracks(&mut tracks, &extras, &modes, &mut tx, &mut moves, dry_run).await
+    {
+        Ok(left) => tx.commit().await.map(|_| left).map_err(|e| eyre!(e)),
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(left) => clean_folders(&left)?,
+        Err(e) => {
+            revert_moves(&moves);
+            return Err(e);
         }
     }
     info!("Done, took {:?}", start.elapsed());
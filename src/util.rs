@@ -1,6 +1,7 @@
 use chrono::NaiveDate;
 use eyre::{eyre, Result};
-use std::fs::create_dir_all;
+use sha2::{Digest, Sha256};
+use std::fs::{create_dir_all, read_dir, remove_dir, File};
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
@@ -26,6 +27,30 @@ pub fn mkdirp<P: AsRef<Path>>(path: P) -> io::Result<()> {
     Ok(())
 }
 
+// Removes the given directory and its parents for as long as they are empty,
+// never going outside of (or removing) the root
+pub fn remove_empty_dirs<P: AsRef<Path>, R: AsRef<Path>>(path: P, root: R) -> io::Result<()> {
+    let mut dir = path.as_ref();
+    while dir.starts_with(root.as_ref()) && dir != root.as_ref() {
+        if read_dir(dir)?.next().is_some() {
+            break;
+        }
+        remove_dir(dir)?;
+        dir = match dir.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    Ok(())
+}
+
+// Hex encoded SHA-256 of the content of a file
+pub fn hash_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 pub fn maybe_date(d: Option<String>) -> Option<NaiveDate> {
     d.and_then(|s| {
         NaiveDate::parse_from_str(s.as_str(), "%Y-%m-%d")
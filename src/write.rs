@@ -0,0 +1,70 @@
+use crate::library::{track_filters, Filter};
+use crate::models::{Format, Track};
+use crate::track::diff::report;
+use crate::track::file::TrackFile;
+use crate::track::picture::{read_pictures, same_pictures};
+use crate::SETTINGS;
+use eyre::{eyre, Result, WrapErr};
+use log::{info, trace, warn};
+use std::time::Instant;
+
+static FMT: &str = "{album_artist} - {track_title}";
+
+// Retags the file of the given track with the metadata from the library and
+// the images found in its folder. Returns whether the file needed any change
+pub fn write_track(track: &Track, dry_run: bool) -> Result<bool> {
+    let settings = SETTINGS.get().ok_or(eyre!("Could not read settings"))?;
+    let path = track
+        .path
+        .as_ref()
+        .ok_or_else(|| eyre!("Track {:?} has no path", track.mbid))?;
+    let file = TrackFile::open(path)?;
+    let mut retagged = file.clone();
+    retagged.retag(&settings.tagging, track.clone().try_into()?)?;
+    let pictures = path.parent().map(read_pictures).transpose()?;
+    if let Some(pictures) = pictures.filter(|p| !p.is_empty()) {
+        retagged.set_pictures(pictures)?;
+    }
+
+    let changes = file.diff(&retagged);
+    let pictures_changed = !same_pictures(&file.get_pictures()?, &retagged.get_pictures()?);
+    if changes.is_empty() && !pictures_changed {
+        return Ok(false);
+    }
+    report(path, path, &changes);
+    if pictures_changed {
+        println!("    ~ pictures");
+    }
+    if !dry_run {
+        retagged
+            .write()
+            .wrap_err(eyre!("Could not write tags to track: {:?}", path))?;
+    }
+    Ok(true)
+}
+
+pub async fn write(filters: Vec<&String>, dry_run: bool) -> Result<()> {
+    let start = Instant::now();
+    let tracks = Track::filter(
+        track_filters(filters)?,
+        vec![" ORDER BY tracks.release, tracks.disc, tracks.number".to_string()],
+    )
+    .await?;
+    let mut changed = 0;
+    for track in tracks.iter() {
+        trace!("Writing track {:?}", track);
+        match write_track(track, dry_run) {
+            Ok(true) => changed += 1,
+            Ok(false) => {}
+            Err(e) => warn!("Could not write track \"{}\": {}", track.fmt(FMT)?, e),
+        }
+    }
+    info!(
+        "{} out of {} tracks {}, took {:?}",
+        changed,
+        tracks.len(),
+        if dry_run { "need changes" } else { "written" },
+        start.elapsed()
+    );
+    Ok(())
+}